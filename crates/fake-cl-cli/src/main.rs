@@ -0,0 +1,356 @@
+#![cfg_attr(not(test), warn(unused_crate_dependencies))]
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser};
+use fake_cl::{auth_client, record::replay, reorg::MAX_REORG_DEPTH, runner::Runner};
+use jsonrpsee::{http_client::HttpClientBuilder, server::Server};
+use parking_lot::RwLock;
+use reth::{
+    chainspec::{ChainSpec, EthereumChainSpecParser},
+    rpc::{
+        api::EthApiClient,
+        types::{Block, BlockNumberOrTag, Receipt, Transaction},
+    },
+};
+use reth_cli::chainspec::ChainSpecParser;
+use reth_node_ethereum::EthEngineTypes;
+use reth_tracing::{
+    tracing::{info, warn},
+    RethTracer, Tracer,
+};
+use std::{ffi::OsString, net::SocketAddr, path::PathBuf, sync::Arc};
+use tester_common::{
+    node::{
+        args::TestArgs,
+        control::{Controller, ReorgReport},
+        ext::{TesterExt, TesterExtApiServer, TesterStatus},
+        stop::StopTracker,
+    },
+    rpc::equality::RpcTester,
+};
+
+#[derive(Debug, Parser)]
+#[command(about = "Drives an out-of-process ethereum execution client through the engine API")]
+pub struct CliArgs {
+    /// Engine API endpoint of the execution client.
+    #[arg(long, value_name = "URL", default_value = "http://localhost:8551")]
+    pub engine_url: String,
+
+    /// Path to the JWT secret shared with the execution client.
+    #[arg(long, value_name = "PATH")]
+    pub jwt_secret: PathBuf,
+
+    /// Chain of the execution client.
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        long_help = EthereumChainSpecParser::help_message(),
+        default_value = EthereumChainSpecParser::SUPPORTED_CHAINS[0],
+        value_parser = EthereumChainSpecParser::parser()
+    )]
+    pub chain: Arc<ChainSpec>,
+
+    /// JSON-RPC endpoint of the execution client. Required by `--against-rpc`.
+    #[arg(long, value_name = "URL")]
+    pub rpc_url: Option<String>,
+
+    /// Replays the engine API requests recorded with `--record` to the execution client instead of
+    /// driving it, and fails if any status differs from the recording.
+    #[arg(long, value_name = "PATH", conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+
+    /// Serves the `tester` namespace on this address, reporting progress through `tester_status`
+    /// and controlling the run, until `tester_stop`, interrupted or `--exit-on-complete`.
+    #[arg(long, value_name = "ADDR")]
+    pub status_addr: Option<SocketAddr>,
+
+    /// Testing arguments shared with the ExEx, without [`TestArgs::EXEX_ONLY_ARGS`].
+    #[command(flatten)]
+    pub test: TestArgs,
+}
+
+impl CliArgs {
+    /// Parses the command line, rejecting the arguments that require an in-process node.
+    fn parse_out_of_process() -> eyre::Result<Self> {
+        Self::parse_out_of_process_from(std::env::args_os())
+    }
+
+    /// Parses `args` like [`Self::parse_out_of_process`].
+    fn parse_out_of_process_from(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    ) -> eyre::Result<Self> {
+        let matches = Self::command()
+            .mut_args(|arg| {
+                let exex_only = TestArgs::EXEX_ONLY_ARGS.contains(&arg.get_id().as_str());
+                arg.hide(exex_only)
+            })
+            .get_matches_from(args);
+        if let Some(id) = TestArgs::EXEX_ONLY_ARGS
+            .iter()
+            .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            eyre::bail!("--{} requires an in-process node", id.replace('_', "-"))
+        }
+
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    RethTracer::new().init()?;
+
+    let args = CliArgs::parse_out_of_process()?;
+    if args.test.against_rpc.is_some() && args.rpc_url.is_none() {
+        eyre::bail!("--against-rpc requires --rpc-url")
+    }
+    if args.test.stop_at.iter().any(|condition| condition.requires_storage()) {
+        eyre::bail!("in-memory and flushes stop conditions require an in-process node")
+    }
+
+    if let Some(path) = &args.replay {
+        let mismatches = replay(&auth_client(&args.engine_url, &args.jwt_secret)?, path).await?;
+        if !mismatches.is_empty() {
+            eyre::bail!("{} replayed calls diverged from the recording", mismatches.len())
+        }
+        info!(path = %path.display(), "Replay matched the recording");
+        return Ok(())
+    }
+
+    let (rpc_ext, control) = TesterExt::new();
+    let status = rpc_ext.watcher.clone();
+    let server = match args.status_addr {
+        Some(addr) => {
+            let server = Server::builder().build(addr).await?;
+            info!(addr = %server.local_addr()?, "Serving tester namespace");
+            Some(server.start(rpc_ext.into_rpc()))
+        }
+        None => None,
+    };
+
+    // Without a status server, no command can move the chain further after the stop condition
+    let exit_on_complete = args.test.exit_on_complete || server.is_none();
+    tokio::select! {
+        res = drive(args, status, Controller::new(control), exit_on_complete) => res?,
+        _ = tokio::signal::ctrl_c() => info!("Interrupted"),
+    }
+
+    if let Some(server) = server {
+        server.stop()?;
+    }
+    Ok(())
+}
+
+/// Moves the execution client chain forward **until** one of its stop conditions is met, and runs
+/// the [`RpcTester`] if `--against-rpc` is set.
+///
+/// Unless `exit_on_complete`, it then waits for
+/// [`ControlCommand`](tester_common::node::control::ControlCommand)s moving the chain further, and
+/// only returns on `tester_stop`.
+///
+/// Only ethereum execution clients can be driven, through the [`EthEngineTypes`] engine API.
+async fn drive(
+    args: CliArgs,
+    status: Arc<RwLock<TesterStatus>>,
+    mut control: Controller,
+    exit_on_complete: bool,
+) -> eyre::Result<()> {
+    let stop_conditions = args.test.stop_conditions();
+    let TestArgs { ref against_rpc, reorg_every, reorg_depth, bench, devnet, .. } = args.test;
+    let auth_client = auth_client(&args.engine_url, &args.jwt_secret)?;
+    let fake_cl = args.test.fake_cl(args.chain.as_ref())?;
+
+    let initial_height: u64 =
+        EthApiClient::<Transaction, Block, Receipt>::block_number(&auth_client)
+            .await?
+            .try_into()?;
+    let finalized_hash = EthApiClient::<Transaction, Block, Receipt>::block_by_number(
+        &auth_client,
+        BlockNumberOrTag::Finalized,
+        false,
+    )
+    .await?
+    .map(|block| block.header.hash)
+    .unwrap_or_default();
+
+    let mut stop = StopTracker::new(stop_conditions, initial_height);
+    let mut runner = Runner::new(fake_cl, initial_height, bench).await?;
+    let mut completed = false;
+    // Whether a competing branch left canonical by `tester_reorg` awaits the next submitted block
+    let mut pending_reorg = false;
+
+    status.write().initial_height = initial_height;
+
+    info!(
+        local_tip = initial_height,
+        source_tip = runner.source_tip(),
+        %finalized_hash,
+        "Starting fake-cl."
+    );
+    loop {
+        let local_tip = runner.local_tip();
+        control.poll(local_tip);
+        if let Some(target) = control.take_target() {
+            stop.set_target(target);
+        }
+
+        // Injects the reorgs requested through `tester_reorg`
+        while let Some((depth, switch_back, reply)) = control.take_reorg() {
+            if pending_reorg {
+                let _ = reply.send(Err("a reorg is already canonical until the next block".into()));
+                continue
+            }
+            // Devnet blocks are built on top of the node head, so they would extend the branch
+            if devnet && !switch_back {
+                let _ = reply.send(Err("devnet reorgs must switch back to the built chain".into()));
+                continue
+            }
+            // The execution client is out-of-process, so only blocks submitted by this run can be
+            // replaced
+            let submitted = local_tip - initial_height;
+            if depth == 0 || depth > submitted.min(MAX_REORG_DEPTH as u64) {
+                let _ = reply.send(Err(format!(
+                    "reorg depth must be between 1 and {}",
+                    submitted.min(MAX_REORG_DEPTH as u64)
+                )));
+                continue
+            }
+
+            let fake_cl = runner.fake_cl_mut();
+            let reorg =
+                match fake_cl.reorg::<EthEngineTypes>(&auth_client, depth, finalized_hash).await {
+                    Ok(reorg) => reorg,
+                    Err(err) => {
+                        let _ = reply.send(Err(err.to_string()));
+                        return Err(err)
+                    }
+                };
+            if switch_back {
+                if let Err(err) = fake_cl
+                    .switch_back::<EthEngineTypes>(&auth_client, &reorg, finalized_hash)
+                    .await
+                {
+                    let _ = reply.send(Err(err.to_string()));
+                    return Err(err)
+                }
+            } else {
+                pending_reorg = true;
+                status.write().reorg = Some(reorg.clone());
+            }
+            let _ = reply.send(Ok(ReorgReport { reorg, switched_back: switch_back }));
+        }
+
+        {
+            let mut status = status.write();
+            status.tip = local_tip;
+            status.paused = control.is_paused();
+        }
+
+        // Stepping through `tester_advance` ignores stop conditions
+        let condition = stop.met(local_tip).filter(|_| !control.is_stepping());
+
+        // Have met a stop condition so we can complete the run, once
+        if condition.is_some() || control.is_stopped() {
+            if !completed {
+                info!(
+                    source_tip = runner.source_tip(),
+                    local_tip,
+                    initial_height,
+                    ?condition,
+                    "Stopped moving chain forward"
+                );
+                completed = true;
+                {
+                    let mut status = status.write();
+                    status.ready = true;
+                    if let Some(summary) = runner.take_bench_summary() {
+                        status.bench = Some(summary);
+                    }
+                }
+
+                if let (Some(remote_url), Some(rpc_url)) = (against_rpc, &args.rpc_url) {
+                    RpcTester::new(
+                        HttpClientBuilder::default().build(rpc_url)?,
+                        HttpClientBuilder::default().build(remote_url)?,
+                    )
+                    .test_equality(initial_height..=local_tip)
+                    .await?;
+                }
+            }
+
+            if exit_on_complete || control.is_stopped() {
+                return Ok(())
+            }
+        }
+
+        // Waits for control commands moving the chain further
+        if condition.is_some() || control.is_paused() {
+            status.write().ready = true;
+            control.wait(local_tip).await;
+            continue
+        }
+
+        if !runner.is_caught_up() {
+            status.write().ready = false;
+
+            // Updates the `tester/status` with the payload outcome
+            let outcome = runner
+                .advance::<EthEngineTypes>(&auth_client, finalized_hash, |outcome| {
+                    status.write().last_payload = Some(outcome.clone())
+                })
+                .await?;
+            stop.record(&outcome);
+            let local_tip = runner.local_tip();
+
+            // A block on top of the source chain replaces the branch left by `tester_reorg`
+            if std::mem::take(&mut pending_reorg) {
+                status.write().reorg = None;
+            }
+
+            // The execution client is out-of-process, so only reorgs of blocks submitted by this
+            // run are injected
+            if reorg_every.is_some_and(|every| (local_tip - initial_height) % every == 0) {
+                if reorg_depth > local_tip - initial_height {
+                    warn!(reorg_depth, local_tip, initial_height, "Skipping reorg beyond this run");
+                } else {
+                    let fake_cl = runner.fake_cl_mut();
+                    let reorg = fake_cl
+                        .reorg::<EthEngineTypes>(&auth_client, reorg_depth, finalized_hash)
+                        .await?;
+                    fake_cl
+                        .switch_back::<EthEngineTypes>(&auth_client, &reorg, finalized_hash)
+                        .await?;
+                }
+            }
+        }
+
+        // Only polls the source once caught up to it, requests are paced by the block source
+        if runner.is_caught_up() {
+            runner.poll_source().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_out_of_process_args() {
+        let args =
+            CliArgs::parse_out_of_process_from(["fake-cl", "--jwt-secret", "jwt.hex"]).unwrap();
+        assert_eq!(args.engine_url, "http://localhost:8551");
+        assert!(!args.test.verify_notifications);
+    }
+
+    #[test]
+    fn rejects_exex_only_args() {
+        for (args, flag) in [
+            (&["--verify-notifications"][..], "--verify-notifications"),
+            (&["--exex-replay"][..], "--exex-replay"),
+            (&["--notification-timeout", "30"][..], "--notification-timeout"),
+        ] {
+            let args = ["fake-cl", "--jwt-secret", "jwt.hex"].iter().chain(args);
+            let err = CliArgs::parse_out_of_process_from(args).unwrap_err();
+            assert_eq!(err.to_string(), format!("{flag} requires an in-process node"));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::status::PayloadOutcome;
+use serde::Serialize;
+use std::{fmt, time::Duration};
+
+/// Collects engine API latencies of every submitted payload.
+#[derive(Debug, Clone, Default)]
+pub struct Bench {
+    new_payload: Vec<Duration>,
+    forkchoice_updated: Vec<Duration>,
+    gas_used: u64,
+}
+
+/// Latency distribution of an engine API method.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    /// Average latency.
+    pub mean: Duration,
+    /// Median latency.
+    pub p50: Duration,
+    /// 90th percentile latency.
+    pub p90: Duration,
+    /// 99th percentile latency.
+    pub p99: Duration,
+    /// Highest latency.
+    pub max: Duration,
+}
+
+/// Engine performance over a run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BenchSummary {
+    /// Number of blocks submitted.
+    pub blocks: usize,
+    /// Gas used by all blocks.
+    pub gas_used: u64,
+    /// Gas executed per second of `newPayload`.
+    pub gas_per_second: f64,
+    /// `newPayload` latencies.
+    pub new_payload: LatencyStats,
+    /// `forkchoiceUpdated` latencies.
+    pub forkchoice_updated: LatencyStats,
+}
+
+impl Bench {
+    /// Records the latencies of a `VALID` payload.
+    pub fn record(&mut self, outcome: &PayloadOutcome) {
+        self.new_payload.push(outcome.new_payload_latency);
+        if let Some(latency) = outcome.forkchoice_updated_latency {
+            self.forkchoice_updated.push(latency);
+        }
+        self.gas_used += outcome.gas_used;
+    }
+
+    /// Returns the [`BenchSummary`] of every recorded payload.
+    pub fn summary(&self) -> BenchSummary {
+        let total: Duration = self.new_payload.iter().sum();
+        BenchSummary {
+            blocks: self.new_payload.len(),
+            gas_used: self.gas_used,
+            gas_per_second: if total.is_zero() {
+                0.0
+            } else {
+                self.gas_used as f64 / total.as_secs_f64()
+            },
+            new_payload: LatencyStats::new(&self.new_payload),
+            forkchoice_updated: LatencyStats::new(&self.forkchoice_updated),
+        }
+    }
+}
+
+impl LatencyStats {
+    /// Computes the distribution of `latencies`.
+    fn new(latencies: &[Duration]) -> Self {
+        if latencies.is_empty() {
+            return Self::default()
+        }
+
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        // Nearest-rank percentile
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+
+        Self {
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            self.mean, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+impl fmt::Display for BenchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} blocks, {} gas, {:.2} Mgas/s",
+            self.blocks,
+            self.gas_used,
+            self.gas_per_second / 1_000_000.0
+        )?;
+        writeln!(f, "newPayload: {}", self.new_payload)?;
+        write!(f, "forkchoiceUpdated: {}", self.forkchoice_updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::BlockHash;
+    use reth::rpc::types::engine::{PayloadStatus, PayloadStatusEnum};
+
+    fn outcome(new_payload: u64, forkchoice_updated: Option<u64>, gas_used: u64) -> PayloadOutcome {
+        PayloadOutcome {
+            block_number: 1,
+            block_hash: BlockHash::ZERO,
+            new_payload: PayloadStatus::from_status(PayloadStatusEnum::Valid),
+            forkchoice_updated: None,
+            new_payload_latency: Duration::from_millis(new_payload),
+            forkchoice_updated_latency: forkchoice_updated.map(Duration::from_millis),
+            gas_used,
+            transaction_count: 0,
+            attempts: 1,
+            mutations: Vec::new(),
+            divergences: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        // shuffled, to check that latencies are sorted
+        let latencies: Vec<_> = (1..=100)
+            .rev()
+            .step_by(2)
+            .chain((1..=100).step_by(2))
+            .map(Duration::from_millis)
+            .collect();
+        let stats = LatencyStats::new(&latencies);
+
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+        assert_eq!(stats.p50, Duration::from_millis(50));
+        assert_eq!(stats.p90, Duration::from_millis(90));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.max, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn handles_few_latencies() {
+        let stats = LatencyStats::new(&[]);
+        assert_eq!(stats.max, Duration::ZERO);
+
+        let stats = LatencyStats::new(&[Duration::from_millis(7)]);
+        assert_eq!(stats.p50, Duration::from_millis(7));
+        assert_eq!(stats.p99, Duration::from_millis(7));
+
+        let stats = LatencyStats::new(&[Duration::from_millis(3), Duration::from_millis(1)]);
+        assert_eq!(stats.mean, Duration::from_millis(2));
+        assert_eq!(stats.p50, Duration::from_millis(1));
+        assert_eq!(stats.p90, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn summarizes_recorded_payloads() {
+        let mut bench = Bench::default();
+        assert_eq!(bench.summary().gas_per_second, 0.0);
+
+        bench.record(&outcome(100, Some(10), 30_000_000));
+        bench.record(&outcome(400, None, 20_000_000));
+        let summary = bench.summary();
+
+        assert_eq!(summary.blocks, 2);
+        assert_eq!(summary.gas_used, 50_000_000);
+        assert_eq!(summary.gas_per_second, 100_000_000.0);
+        assert_eq!(summary.new_payload.max, Duration::from_millis(400));
+        assert_eq!(summary.forkchoice_updated.max, Duration::from_millis(10));
+        assert_eq!(
+            summary.to_string().lines().next(),
+            Some("2 blocks, 50000000 gas, 100.00 Mgas/s")
+        );
+    }
+}
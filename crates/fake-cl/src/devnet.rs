@@ -0,0 +1,500 @@
+use crate::payload::{EngineVersion, ForkSchedule, NewPayload};
+use alloy_consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_primitives::{keccak256, Address, BlockHash, BlockNumber, Bytes, TxKind, B256, U256};
+use alloy_signer::SignerSync;
+use alloy_signer_local::{coins_bip39::English, MnemonicBuilder, PrivateKeySigner};
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{transport::HttpBackend, HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use reth::rpc::types::{
+    engine::{
+        ExecutionPayloadEnvelopeV2, ExecutionPayloadEnvelopeV3, ExecutionPayloadEnvelopeV4,
+        ExecutionPayloadFieldV2, ExecutionPayloadV1, ExecutionPayloadV2, ExecutionPayloadV3,
+        ForkchoiceState, ForkchoiceUpdated, PayloadAttributes,
+    },
+    Block, BlockNumberOrTag,
+};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::{debug, warn};
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Mnemonic of the accounts pre-funded by the reth `dev` chain.
+pub const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Runtime code emitting a `LOG1` with the caller as topic: `CALLER PUSH1 0 PUSH1 0 LOG1 STOP`.
+const LOG_EMITTER_CODE: &[u8] = &[0x33, 0x60, 0x00, 0x60, 0x00, 0xa1, 0x00];
+
+/// Runtime code that always reverts: `PUSH1 0 PUSH1 0 REVERT`.
+const REVERTER_CODE: &[u8] = &[0x60, 0x00, 0x60, 0x00, 0xfd];
+
+/// Max fee per gas on top of the parent base fee.
+const PRIORITY_FEE: u128 = 1_000_000_000;
+
+/// Kind of generated transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxMixKind {
+    /// Plain value transfer between dev accounts.
+    Transfer,
+    /// Deploys a log emitting or a reverting contract, alternately.
+    Deploy,
+    /// Calls a log emitting contract.
+    Log,
+    /// Calls a reverting contract.
+    Revert,
+}
+
+/// Proportions of each kind of generated transaction.
+///
+/// Parsed from `transfers=<N>,deploys=<N>,logs=<N>,reverts=<N>`, where missing kinds default to
+/// zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxMix {
+    /// Generated kinds, in order, cycled through forever.
+    kinds: Vec<TxMixKind>,
+}
+
+impl FromStr for TxMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = Vec::new();
+        for entry in s.split(',') {
+            let (name, weight) =
+                entry.split_once('=').ok_or_else(|| format!("expected <kind>=<N>, got {entry}"))?;
+            let weight: usize = weight.parse().map_err(|err| format!("invalid {name}: {err}"))?;
+            let kind = match name {
+                "transfers" => TxMixKind::Transfer,
+                "deploys" => TxMixKind::Deploy,
+                "logs" => TxMixKind::Log,
+                "reverts" => TxMixKind::Revert,
+                _ => return Err(format!("unknown transaction kind {name}")),
+            };
+            kinds.extend(std::iter::repeat(kind).take(weight));
+        }
+
+        if kinds.is_empty() {
+            return Err("transaction mix can't be empty".to_string())
+        }
+        Ok(Self { kinds })
+    }
+}
+
+/// Configuration of a [`Devnet`].
+#[derive(Debug, Clone)]
+pub struct DevnetConfig {
+    /// JSON-RPC endpoint of the node, used to query nonces and submit transactions.
+    pub rpc_url: String,
+    /// Chain id of the devnet.
+    pub chain_id: u64,
+    /// Mnemonic of the pre-funded accounts.
+    pub mnemonic: String,
+    /// Number of pre-funded accounts derived from the mnemonic.
+    pub accounts: u32,
+    /// Number of transactions submitted before building each block.
+    pub txs_per_block: usize,
+    /// Proportions of each kind of generated transaction.
+    pub tx_mix: TxMix,
+    /// Time given to the node payload builder to include the submitted transactions before
+    /// fetching the block.
+    pub build_time: Duration,
+}
+
+/// Builds blocks locally by asking the node to build them through `forkchoiceUpdated` with payload
+/// attributes and `getPayload`, filled with transactions from pre-funded dev accounts.
+///
+/// Only supported on ethereum chains, since optimism blocks require deposit transactions.
+#[derive(Debug)]
+pub struct Devnet {
+    /// Node JSON-RPC client.
+    rpc: HttpClient,
+    chain_id: u64,
+    signers: Vec<PrivateKeySigner>,
+    /// Next nonce of each signer, fetched from the node on first use.
+    nonces: Vec<Option<u64>>,
+    txs_per_block: usize,
+    tx_mix: TxMix,
+    build_time: Duration,
+    /// Number of generated transactions so far.
+    generated: usize,
+    /// Deployed log emitting contracts.
+    log_emitters: Vec<Address>,
+    /// Deployed reverting contracts.
+    reverters: Vec<Address>,
+}
+
+impl Devnet {
+    /// Creates a [`Self`] from a [`DevnetConfig`].
+    pub fn new(config: DevnetConfig) -> Result<Self, eyre::Error> {
+        let signers = (0..config.accounts)
+            .map(|index| {
+                MnemonicBuilder::<English>::default()
+                    .phrase(config.mnemonic.as_str())
+                    .index(index)?
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if signers.is_empty() {
+            eyre::bail!("devnet requires at least one account")
+        }
+
+        Ok(Self {
+            rpc: HttpClientBuilder::default().build(&config.rpc_url)?,
+            chain_id: config.chain_id,
+            nonces: vec![None; signers.len()],
+            signers,
+            txs_per_block: config.txs_per_block,
+            tx_mix: config.tx_mix,
+            build_time: config.build_time,
+            generated: 0,
+            log_emitters: Vec::new(),
+            reverters: Vec::new(),
+        })
+    }
+
+    /// Submits generated transactions and asks the node to build block `block_number` on top of
+    /// its parent.
+    ///
+    /// Fails if the built block includes none of the submitted transactions.
+    pub(crate) async fn build_payload(
+        &mut self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        block_number: BlockNumber,
+        fork_schedule: &ForkSchedule,
+        finalized_hash: BlockHash,
+    ) -> Result<NewPayload, eyre::Error> {
+        let parent: Block = auth_client
+            .request(
+                "eth_getBlockByNumber",
+                rpc_params![BlockNumberOrTag::Number(block_number - 1), false],
+            )
+            .await?;
+
+        self.submit_transactions(parent.header.base_fee_per_gas.unwrap_or_default()).await?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let timestamp = now.max(parent.header.timestamp + 1);
+        let version = fork_schedule.version_at(timestamp);
+        let parent_beacon_block_root = keccak256(block_number.to_be_bytes());
+        let attributes = PayloadAttributes {
+            timestamp,
+            prev_randao: keccak256(parent.header.hash),
+            suggested_fee_recipient: self.signers[0].address(),
+            withdrawals: (version >= EngineVersion::V2).then(Vec::new),
+            parent_beacon_block_root: (version >= EngineVersion::V3)
+                .then_some(parent_beacon_block_root),
+        };
+        let state = ForkchoiceState {
+            head_block_hash: parent.header.hash,
+            safe_block_hash: finalized_hash,
+            finalized_block_hash: finalized_hash,
+        };
+
+        let updated: ForkchoiceUpdated =
+            auth_client.request(version.fcu_method(), rpc_params![state, attributes]).await?;
+        let payload_id = updated.payload_id.ok_or_else(|| {
+            eyre::eyre!("node did not start building block {block_number}: {updated:?}")
+        })?;
+
+        // `getPayload` returns the best block built so far, which is the empty block until the
+        // payload builder has picked up the submitted transactions
+        tokio::time::sleep(self.build_time).await;
+
+        // Earlier payload versions are carried as a V3 payload, like `NewPayload::from_block`
+        let v3 = |payload_inner| ExecutionPayloadV3 {
+            payload_inner,
+            blob_gas_used: 0,
+            excess_blob_gas: 0,
+        };
+        let (payload, execution_requests) = match version {
+            EngineVersion::V1 => {
+                let payload: ExecutionPayloadV1 =
+                    auth_client.request("engine_getPayloadV1", rpc_params![payload_id]).await?;
+                (v3(ExecutionPayloadV2 { payload_inner: payload, withdrawals: Vec::new() }), None)
+            }
+            EngineVersion::V2 => {
+                let envelope: ExecutionPayloadEnvelopeV2 =
+                    auth_client.request("engine_getPayloadV2", rpc_params![payload_id]).await?;
+                let payload = match envelope.execution_payload {
+                    ExecutionPayloadFieldV2::V1(payload) => {
+                        ExecutionPayloadV2 { payload_inner: payload, withdrawals: Vec::new() }
+                    }
+                    ExecutionPayloadFieldV2::V2(payload) => payload,
+                };
+                (v3(payload), None)
+            }
+            EngineVersion::V3 => {
+                let envelope: ExecutionPayloadEnvelopeV3 =
+                    auth_client.request("engine_getPayloadV3", rpc_params![payload_id]).await?;
+                (envelope.execution_payload, None)
+            }
+            EngineVersion::V4 => {
+                let envelope: ExecutionPayloadEnvelopeV4 =
+                    auth_client.request("engine_getPayloadV4", rpc_params![payload_id]).await?;
+                (envelope.envelope_inner.execution_payload, Some(envelope.execution_requests))
+            }
+        };
+
+        let new_payload = NewPayload {
+            version,
+            payload,
+            // Generated transactions carry no blobs
+            versioned_hashes: Vec::new(),
+            parent_beacon_block_root,
+            execution_requests: execution_requests.unwrap_or_default(),
+        };
+
+        let txs = new_payload.payload.payload_inner.payload_inner.transactions.len();
+        if txs == 0 && self.txs_per_block > 0 {
+            eyre::bail!(
+                "node built block {block_number} without any of the {} submitted transactions, try a longer build time",
+                self.txs_per_block
+            )
+        }
+        if txs < self.txs_per_block {
+            warn!(
+                target: "exex-consensus",
+                block_number,
+                txs,
+                submitted = self.txs_per_block,
+                "Devnet block misses submitted transactions"
+            );
+        }
+
+        debug!(
+            target: "exex-consensus",
+            block_number,
+            block_hash = %new_payload.block_hash(),
+            txs,
+            "Built devnet block"
+        );
+        Ok(new_payload)
+    }
+
+    /// Signs and submits `txs_per_block` generated transactions to the node transaction pool.
+    async fn submit_transactions(&mut self, base_fee: u64) -> Result<(), eyre::Error> {
+        for _ in 0..self.txs_per_block {
+            let index = self.generated % self.signers.len();
+            let kind = self.tx_mix.kinds[self.generated % self.tx_mix.kinds.len()];
+            self.generated += 1;
+
+            let sender = self.signers[index].address();
+            let nonce = match self.nonces[index] {
+                Some(nonce) => nonce,
+                None => {
+                    let nonce: U256 = self
+                        .rpc
+                        .request("eth_getTransactionCount", rpc_params![sender, "pending"])
+                        .await?;
+                    nonce.try_into()?
+                }
+            };
+
+            let (to, input, gas_limit) = match kind {
+                TxMixKind::Transfer => {
+                    let to = self.signers[(index + 1) % self.signers.len()].address();
+                    (TxKind::Call(to), Bytes::new(), 21_000)
+                }
+                TxMixKind::Log if !self.log_emitters.is_empty() => {
+                    let to = self.log_emitters[self.generated % self.log_emitters.len()];
+                    (TxKind::Call(to), Bytes::new(), 50_000)
+                }
+                TxMixKind::Revert if !self.reverters.is_empty() => {
+                    let to = self.reverters[self.generated % self.reverters.len()];
+                    (TxKind::Call(to), Bytes::new(), 50_000)
+                }
+                // Deploys whatever contract is missing, or alternates between both.
+                TxMixKind::Deploy | TxMixKind::Log | TxMixKind::Revert => {
+                    let contract = sender.create(nonce);
+                    let code = if kind == TxMixKind::Revert ||
+                        (kind == TxMixKind::Deploy &&
+                            self.reverters.len() < self.log_emitters.len())
+                    {
+                        self.reverters.push(contract);
+                        REVERTER_CODE
+                    } else {
+                        self.log_emitters.push(contract);
+                        LOG_EMITTER_CODE
+                    };
+                    (TxKind::Create, init_code(code), 100_000)
+                }
+            };
+
+            let tx = TxEip1559 {
+                chain_id: self.chain_id,
+                nonce,
+                gas_limit,
+                max_fee_per_gas: 2 * base_fee as u128 + PRIORITY_FEE,
+                max_priority_fee_per_gas: PRIORITY_FEE,
+                to,
+                value: U256::from(matches!(kind, TxMixKind::Transfer) as u8),
+                access_list: Default::default(),
+                input,
+            };
+            let signature = self.signers[index].sign_hash_sync(&tx.signature_hash())?;
+            let tx: TxEnvelope = tx.into_signed(signature).into();
+
+            let _: B256 = self
+                .rpc
+                .request("eth_sendRawTransaction", rpc_params![Bytes::from(tx.encoded_2718())])
+                .await?;
+            self.nonces[index] = Some(nonce + 1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the init code deploying `runtime`: `PUSH1 len DUP1 PUSH1 11 PUSH1 0 CODECOPY PUSH1 0
+/// RETURN` followed by `runtime`.
+fn init_code(runtime: &[u8]) -> Bytes {
+    let mut code =
+        vec![0x60, runtime.len() as u8, 0x80, 0x60, 0x0b, 0x60, 0x00, 0x39, 0x60, 0x00, 0xf3];
+    code.extend_from_slice(runtime);
+    code.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpsee::{
+        server::{Server, ServerHandle},
+        types::ErrorObjectOwned,
+        RpcModule,
+    };
+    use reth::{
+        chainspec::ChainSpec,
+        rpc::types::engine::{PayloadId, PayloadStatusEnum},
+    };
+    use reth_rpc_layer::{AuthClientLayer, JwtSecret};
+    use std::{sync::Mutex, time::Instant};
+
+    /// Transaction pool and payload builder of the mock node.
+    #[derive(Default)]
+    struct Pool {
+        txs: Vec<Bytes>,
+        /// When the node was last asked to build a block.
+        building_since: Option<Instant>,
+    }
+
+    /// Serves a node whose payload builder includes the pooled transactions `build_interval` after
+    /// being asked to build a block, and returns a [`Devnet`] submitting 3 transactions per block
+    /// to it along with an engine API client.
+    async fn node(
+        build_interval: Duration,
+        build_time: Duration,
+    ) -> (Devnet, HttpClient<AuthClientService<HttpBackend>>, ServerHandle) {
+        let mut module = RpcModule::new(Mutex::new(Pool::default()));
+        module
+            .register_method("eth_getBlockByNumber", |_, _, _| {
+                Ok::<Block, ErrorObjectOwned>(Block::default())
+            })
+            .unwrap();
+        module
+            .register_method("eth_getTransactionCount", |_, _, _| {
+                Ok::<_, ErrorObjectOwned>(U256::ZERO)
+            })
+            .unwrap();
+        module
+            .register_method("eth_sendRawTransaction", |params, pool, _| {
+                let tx: Bytes = params.one()?;
+                pool.lock().unwrap().txs.push(tx.clone());
+                Ok::<_, ErrorObjectOwned>(keccak256(tx))
+            })
+            .unwrap();
+        module
+            .register_method("engine_forkchoiceUpdatedV1", |_, pool, _| {
+                pool.lock().unwrap().building_since = Some(Instant::now());
+                Ok::<_, ErrorObjectOwned>(
+                    ForkchoiceUpdated::from_status(PayloadStatusEnum::Valid)
+                        .with_payload_id(PayloadId::new([1; 8])),
+                )
+            })
+            .unwrap();
+        module
+            .register_method("engine_getPayloadV1", move |_, pool, _| {
+                let pool = pool.lock().unwrap();
+                let built =
+                    pool.building_since.is_some_and(|since| since.elapsed() >= build_interval);
+                Ok::<_, ErrorObjectOwned>(ExecutionPayloadV1 {
+                    parent_hash: B256::ZERO,
+                    fee_recipient: Address::ZERO,
+                    state_root: B256::ZERO,
+                    receipts_root: B256::ZERO,
+                    logs_bloom: Default::default(),
+                    prev_randao: B256::ZERO,
+                    block_number: 1,
+                    gas_limit: 30_000_000,
+                    gas_used: 0,
+                    timestamp: 0,
+                    extra_data: Bytes::new(),
+                    base_fee_per_gas: U256::ZERO,
+                    block_hash: B256::ZERO,
+                    transactions: if built { pool.txs.clone() } else { Vec::new() },
+                })
+            })
+            .unwrap();
+
+        let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let middleware =
+            tower::ServiceBuilder::default().layer(AuthClientLayer::new(JwtSecret::random()));
+        let auth_client =
+            HttpClientBuilder::default().set_http_middleware(middleware).build(&url).unwrap();
+        let devnet = Devnet::new(DevnetConfig {
+            rpc_url: url,
+            chain_id: 1,
+            mnemonic: DEV_MNEMONIC.to_string(),
+            accounts: 2,
+            txs_per_block: 3,
+            tx_mix: "transfers=1".parse().unwrap(),
+            build_time,
+        })
+        .unwrap();
+        (devnet, auth_client, server.start(module))
+    }
+
+    #[tokio::test]
+    async fn blocks_include_the_submitted_transactions() {
+        let interval = Duration::from_millis(50);
+        let (mut devnet, auth_client, _server) = node(interval, interval).await;
+        // No hardfork is active, so blocks are built with the V1 engine API
+        let schedule = ForkSchedule::new(&ChainSpec::default());
+
+        let payload =
+            devnet.build_payload(&auth_client, 1, &schedule, BlockHash::ZERO).await.unwrap();
+        assert_eq!(payload.payload.payload_inner.payload_inner.transactions.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_blocks_without_the_submitted_transactions() {
+        let (mut devnet, auth_client, _server) =
+            node(Duration::from_secs(60), Duration::ZERO).await;
+        let schedule = ForkSchedule::new(&ChainSpec::default());
+
+        let err =
+            devnet.build_payload(&auth_client, 1, &schedule, BlockHash::ZERO).await.unwrap_err();
+        assert!(err.to_string().contains("without any of the 3 submitted transactions"), "{err}");
+    }
+
+    #[test]
+    fn parses_transaction_mix() {
+        use TxMixKind::*;
+
+        assert_eq!("transfers=2".parse::<TxMix>(), Ok(TxMix { kinds: vec![Transfer, Transfer] }));
+        assert_eq!(
+            "transfers=1,deploys=1,logs=2,reverts=1".parse::<TxMix>(),
+            Ok(TxMix { kinds: vec![Transfer, Deploy, Log, Log, Revert] })
+        );
+        assert_eq!("logs=0,reverts=1".parse::<TxMix>(), Ok(TxMix { kinds: vec![Revert] }));
+        assert!("transfers=0".parse::<TxMix>().is_err());
+        assert!("transfers".parse::<TxMix>().is_err());
+        assert!("transfers=-1".parse::<TxMix>().is_err());
+        assert!("mints=1".parse::<TxMix>().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    forkchoice_updated, new_payload,
+    payload::{EngineVersion, NewPayload, PayloadTransaction},
+    FakeCl,
+};
+use alloy_primitives::{BlockHash, BlockNumber};
+use jsonrpsee::http_client::{transport::HttpBackend, HttpClient};
+use reth::{
+    api::EngineTypes,
+    rpc::types::engine::{ForkchoiceState, PayloadStatus},
+};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::warn;
+use serde::Serialize;
+
+/// An execution client that receives the same engine API calls as the driven node.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    /// Name used when reporting divergences, eg. its engine URL.
+    pub name: String,
+    /// Authenticated engine API client.
+    pub client: HttpClient<AuthClientService<HttpBackend>>,
+}
+
+/// A peer answer differing from the driven node.
+#[derive(Debug, Clone, Serialize)]
+pub struct Divergence {
+    /// Name of the diverging [`Peer`].
+    pub peer: String,
+    /// Engine API method, `newPayload` or `forkchoiceUpdated`.
+    pub method: &'static str,
+    /// Block number of the payload or head.
+    pub block_number: BlockNumber,
+    /// Block hash of the payload or head.
+    pub block_hash: BlockHash,
+    /// Status returned by the driven node.
+    pub expected: PayloadStatus,
+    /// Status returned by the peer, if the request succeeded.
+    pub actual: Option<PayloadStatus>,
+    /// Error of the peer request, if it failed.
+    pub error: Option<String>,
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Submits every payload and forkchoice update to `peers` as well, reporting how their
+    /// statuses diverge from the driven node.
+    pub fn with_peers(mut self, peers: Vec<Peer>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Submits `payload` to every peer and returns how they diverge from `expected`.
+    pub(crate) async fn compare_new_payload<E: EngineTypes>(
+        &self,
+        payload: &NewPayload,
+        expected: &PayloadStatus,
+    ) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        for peer in &self.peers {
+            let result = new_payload::<E>(&peer.client, payload.clone()).await;
+            divergences.extend(diverge(
+                peer,
+                "newPayload",
+                payload.block_number(),
+                payload.block_hash(),
+                expected,
+                result,
+            ));
+        }
+        divergences
+    }
+
+    /// Submits the forkchoice update to every peer and returns how they diverge from `expected`.
+    pub(crate) async fn compare_forkchoice_updated<E: EngineTypes>(
+        &self,
+        version: EngineVersion,
+        state: ForkchoiceState,
+        head_number: BlockNumber,
+        expected: &PayloadStatus,
+    ) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        for peer in &self.peers {
+            let result = forkchoice_updated::<E>(&peer.client, version, state)
+                .await
+                .map(|updated| updated.payload_status);
+            divergences.extend(diverge(
+                peer,
+                "forkchoiceUpdated",
+                head_number,
+                state.head_block_hash,
+                expected,
+                result,
+            ));
+        }
+        divergences
+    }
+}
+
+/// Returns a [`Divergence`] if the peer `result` differs from `expected` in status,
+/// `latestValidHash` or validation error.
+fn diverge(
+    peer: &Peer,
+    method: &'static str,
+    block_number: BlockNumber,
+    block_hash: BlockHash,
+    expected: &PayloadStatus,
+    result: Result<PayloadStatus, eyre::Error>,
+) -> Option<Divergence> {
+    let (actual, error) = match result {
+        Ok(actual) if actual == *expected => return None,
+        Ok(actual) => (Some(actual), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+
+    let divergence = Divergence {
+        peer: peer.name.clone(),
+        method,
+        block_number,
+        block_hash,
+        expected: expected.clone(),
+        actual,
+        error,
+    };
+    warn!(target: "exex-consensus", ?divergence, "Peer diverged");
+    Some(divergence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpsee::http_client::HttpClientBuilder;
+    use reth::rpc::types::engine::PayloadStatusEnum;
+    use reth_rpc_layer::{AuthClientLayer, JwtSecret};
+
+    fn peer() -> Peer {
+        let middleware =
+            tower::ServiceBuilder::default().layer(AuthClientLayer::new(JwtSecret::random()));
+        let client = HttpClientBuilder::default()
+            .set_http_middleware(middleware)
+            .build("http://peer")
+            .unwrap();
+        Peer { name: "peer".to_string(), client }
+    }
+
+    fn diverge_from_valid(result: Result<PayloadStatus, eyre::Error>) -> Option<Divergence> {
+        let expected =
+            PayloadStatus::new(PayloadStatusEnum::Valid, Some(BlockHash::with_last_byte(1)));
+        diverge(&peer(), "newPayload", 1, BlockHash::with_last_byte(1), &expected, result)
+    }
+
+    #[tokio::test]
+    async fn reports_differing_statuses() {
+        let valid =
+            PayloadStatus::new(PayloadStatusEnum::Valid, Some(BlockHash::with_last_byte(1)));
+        assert!(diverge_from_valid(Ok(valid.clone())).is_none());
+
+        let syncing = PayloadStatus::from_status(PayloadStatusEnum::Syncing);
+        let divergence = diverge_from_valid(Ok(syncing.clone())).unwrap();
+        assert_eq!(divergence.peer, "peer");
+        assert_eq!(divergence.expected, valid);
+        assert_eq!(divergence.actual, Some(syncing));
+        assert_eq!(divergence.error, None);
+
+        let other_hash =
+            PayloadStatus::new(PayloadStatusEnum::Valid, Some(BlockHash::with_last_byte(2)));
+        assert!(diverge_from_valid(Ok(other_hash)).is_some());
+    }
+
+    #[tokio::test]
+    async fn reports_failed_requests() {
+        let divergence = diverge_from_valid(Err(eyre::eyre!("connection refused"))).unwrap();
+        assert_eq!(divergence.actual, None);
+        assert_eq!(divergence.error.as_deref(), Some("connection refused"));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{payload::PayloadTransaction, Driver, FakeCl};
+use alloy_eips::BlockNumHash;
+use alloy_primitives::{BlockHash, BlockNumber};
+use reth::rpc::types::{engine::ForkchoiceState, BlockNumberOrTag};
+use reth_tracing::tracing::debug;
+use std::str::FromStr;
+
+/// Maximum number of submitted block hashes kept around to resolve safe and finalized blocks.
+pub(crate) const MAX_TRACKED_HASHES: usize = 1024;
+
+/// How the safe or finalized block of each `forkchoiceUpdated` request is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagPolicy {
+    /// Always the block finalized by the node at startup.
+    #[default]
+    Fixed,
+    /// The block the given number of blocks behind the head.
+    Lag(u64),
+    /// The block as far behind the head as the block tagged by the block source is behind its
+    /// tip, so that tags follow the replayed chain. Not supported by a
+    /// [`Devnet`](crate::devnet::Devnet), which has no source.
+    Mirror,
+}
+
+/// Checks that the safe block chosen by `safe` can never fall behind the finalized block chosen
+/// by `finalized`.
+///
+/// Combinations depending on the block source are instead capped when building each
+/// [`ForkchoiceState`].
+pub fn validate_tag_policies(safe: TagPolicy, finalized: TagPolicy) -> Result<(), eyre::Error> {
+    match (safe, finalized) {
+        (TagPolicy::Fixed, TagPolicy::Lag(_) | TagPolicy::Mirror) => {
+            eyre::bail!("a fixed safe block falls behind a moving finalized block")
+        }
+        (TagPolicy::Lag(safe), TagPolicy::Lag(finalized)) if safe > finalized => eyre::bail!(
+            "a safe block {safe} blocks behind the head falls behind a finalized block {finalized} blocks behind it"
+        ),
+        _ => Ok(()),
+    }
+}
+
+impl FromStr for TagPolicy {
+    type Err = String;
+
+    /// Parses `fixed`, `lag:<N>` or `mirror`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "mirror" => Ok(Self::Mirror),
+            _ => s
+                .strip_prefix("lag:")
+                .and_then(|lag| lag.parse().ok())
+                .map(Self::Lag)
+                .ok_or_else(|| format!("invalid policy {s}: expected fixed, lag:N or mirror")),
+        }
+    }
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Sets the [`TagPolicy`] of the safe and finalized blocks.
+    pub fn with_tag_policies(mut self, safe: TagPolicy, finalized: TagPolicy) -> Self {
+        self.safe_policy = safe;
+        self.finalized_policy = finalized;
+        self
+    }
+
+    /// Returns the [`ForkchoiceState`] making `head` canonical.
+    ///
+    /// Safe and finalized blocks follow their [`TagPolicy`], are never above `max_tag` and fall
+    /// back to `finalized_hash` when they can't be resolved. The finalized block is never above
+    /// the safe block, and falls back along with it.
+    pub(crate) async fn forkchoice_state(
+        &self,
+        head: BlockNumHash,
+        max_tag: BlockNumber,
+        finalized_hash: BlockHash,
+    ) -> Result<ForkchoiceState, eyre::Error> {
+        let source_tip = match &self.driver {
+            Driver::Source(prefetcher)
+                if self.safe_policy == TagPolicy::Mirror ||
+                    self.finalized_policy == TagPolicy::Mirror =>
+            {
+                Some(prefetcher.latest().await?)
+            }
+            _ => None,
+        };
+
+        let safe = self
+            .tag_number(self.safe_policy, BlockNumberOrTag::Safe, head.number, source_tip)
+            .await?
+            .map(|number| number.min(max_tag));
+        let safe_hash = self.tag_hash(BlockNumberOrTag::Safe, safe, head);
+
+        let finalized = match (safe, safe_hash) {
+            (Some(safe), Some(_)) => self
+                .tag_number(
+                    self.finalized_policy,
+                    BlockNumberOrTag::Finalized,
+                    head.number,
+                    source_tip,
+                )
+                .await?
+                .map(|number| number.min(safe)),
+            _ => None,
+        };
+        let finalized_block_hash =
+            self.tag_hash(BlockNumberOrTag::Finalized, finalized, head).unwrap_or(finalized_hash);
+
+        Ok(ForkchoiceState {
+            head_block_hash: head.hash,
+            safe_block_hash: safe_hash.unwrap_or(finalized_hash),
+            finalized_block_hash,
+        })
+    }
+
+    /// Returns the number of the block selected by `policy` for a chain at `head`, if any.
+    ///
+    /// Mirrored tags are as far behind `head` as the source tags are behind `source_tip`.
+    async fn tag_number(
+        &self,
+        policy: TagPolicy,
+        tag: BlockNumberOrTag,
+        head: BlockNumber,
+        source_tip: Option<BlockNumber>,
+    ) -> Result<Option<BlockNumber>, eyre::Error> {
+        Ok(match policy {
+            TagPolicy::Fixed => None,
+            TagPolicy::Lag(lag) => head.checked_sub(lag),
+            TagPolicy::Mirror => match &self.driver {
+                Driver::Source(prefetcher) => {
+                    let tagged = prefetcher.source().tagged(tag).await?;
+                    tagged
+                        .zip(source_tip)
+                        .and_then(|(tagged, tip)| head.checked_sub(tip.saturating_sub(tagged)))
+                }
+                Driver::Devnet(_) => None,
+            },
+        })
+    }
+
+    /// Resolves the hash of the submitted block at `number`, if any.
+    fn tag_hash(
+        &self,
+        tag: BlockNumberOrTag,
+        number: Option<BlockNumber>,
+        head: BlockNumHash,
+    ) -> Option<BlockHash> {
+        let hash = match number {
+            Some(number) if number == head.number => Some(head.hash),
+            Some(number) => self.submitted.get(&number).copied(),
+            None => None,
+        };
+        if number.is_some() && hash.is_none() {
+            debug!(target: "exex-consensus", %tag, ?number, "unresolved tag, using startup finalized block");
+        }
+        hash
+    }
+
+    /// Records a block accepted as canonical.
+    pub(crate) fn track_submitted(&mut self, block: BlockNumHash) {
+        self.submitted.insert(block.number, block.hash);
+        while self.submitted.len() > MAX_TRACKED_HASHES {
+            self.submitted.pop_first();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payload::ForkSchedule, BlockSource};
+    use futures::future::BoxFuture;
+    use reth::{
+        chainspec::MAINNET,
+        rpc::types::{Block, Transaction},
+    };
+
+    /// Block finalized by the node at startup.
+    const STARTUP_FINALIZED: BlockHash = BlockHash::repeat_byte(0xff);
+
+    /// Serves empty blocks up to 100, tagging 90 as safe and 80 as finalized.
+    struct Tagged;
+
+    impl BlockSource for Tagged {
+        fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+            Box::pin(async { Ok(100) })
+        }
+
+        fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+            Box::pin(async move {
+                let mut block = Block::<Transaction>::default();
+                block.header.inner.number = number;
+                Ok(block)
+            })
+        }
+
+        fn tagged(
+            &self,
+            tag: BlockNumberOrTag,
+        ) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+            Box::pin(async move {
+                Ok(match tag {
+                    BlockNumberOrTag::Safe => Some(90),
+                    BlockNumberOrTag::Finalized => Some(80),
+                    _ => None,
+                })
+            })
+        }
+    }
+
+    fn hash(number: BlockNumber) -> BlockHash {
+        BlockHash::with_last_byte(number as u8)
+    }
+
+    /// Returns the [`ForkchoiceState`] of head 50 after submitting blocks 1 to 50 from [`Tagged`].
+    async fn state(safe: TagPolicy, finalized: TagPolicy) -> ForkchoiceState {
+        let mut fake_cl = FakeCl::new(Tagged, ForkSchedule::new(MAINNET.as_ref()))
+            .with_tag_policies(safe, finalized);
+        for number in 1..=50 {
+            fake_cl.track_submitted(BlockNumHash::new(number, hash(number)));
+        }
+        fake_cl
+            .forkchoice_state(BlockNumHash::new(50, hash(50)), 50, STARTUP_FINALIZED)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mirrors_source_tags_behind_the_replayed_head() {
+        let state = state(TagPolicy::Mirror, TagPolicy::Mirror).await;
+        assert_eq!(state.safe_block_hash, hash(40));
+        assert_eq!(state.finalized_block_hash, hash(30));
+    }
+
+    #[tokio::test]
+    async fn finalized_never_passes_safe() {
+        let state = state(TagPolicy::Lag(30), TagPolicy::Mirror).await;
+        assert_eq!(state.safe_block_hash, hash(20));
+        assert_eq!(state.finalized_block_hash, hash(20));
+
+        // The safe block is unresolved, so both fall back to the startup finalized block
+        let state = state(TagPolicy::Lag(60), TagPolicy::Mirror).await;
+        assert_eq!(state.safe_block_hash, STARTUP_FINALIZED);
+        assert_eq!(state.finalized_block_hash, STARTUP_FINALIZED);
+    }
+
+    #[test]
+    fn rejects_safe_behind_finalized() {
+        validate_tag_policies(TagPolicy::Fixed, TagPolicy::Fixed).unwrap();
+        validate_tag_policies(TagPolicy::Lag(2), TagPolicy::Lag(64)).unwrap();
+        validate_tag_policies(TagPolicy::Mirror, TagPolicy::Lag(64)).unwrap();
+        validate_tag_policies(TagPolicy::Lag(2), TagPolicy::Fixed).unwrap();
+
+        assert!(validate_tag_policies(TagPolicy::Fixed, TagPolicy::Lag(64)).is_err());
+        assert!(validate_tag_policies(TagPolicy::Fixed, TagPolicy::Mirror).is_err());
+        assert!(validate_tag_policies(TagPolicy::Lag(64), TagPolicy::Lag(2)).is_err());
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("fixed".parse::<TagPolicy>(), Ok(TagPolicy::Fixed));
+        assert_eq!("mirror".parse::<TagPolicy>(), Ok(TagPolicy::Mirror));
+        assert_eq!("lag:0".parse::<TagPolicy>(), Ok(TagPolicy::Lag(0)));
+        assert_eq!("lag:64".parse::<TagPolicy>(), Ok(TagPolicy::Lag(64)));
+        assert!("lag".parse::<TagPolicy>().is_err());
+        assert!("lag:".parse::<TagPolicy>().is_err());
+        assert!("lag:-1".parse::<TagPolicy>().is_err());
+        assert!("latest".parse::<TagPolicy>().is_err());
+    }
+}
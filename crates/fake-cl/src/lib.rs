@@ -1,79 +1,504 @@
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
-use alloy_chains::Chain;
-use alloy_primitives::BlockHash;
-use jsonrpsee::http_client::{transport::HttpBackend, HttpClient};
+use alloy_eips::BlockNumHash;
+use alloy_primitives::{BlockHash, BlockNumber};
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{transport::HttpBackend, HttpClient, HttpClientBuilder},
+    rpc_params,
+};
 use reth::{
     api::EngineTypes,
-    rpc::{api::EngineApiClient, types::engine::ForkchoiceState},
+    rpc::{
+        api::EngineApiClient,
+        types::{
+            engine::{ExecutionPayloadInputV2, ForkchoiceState, ForkchoiceUpdated, PayloadStatus},
+            Block, Transaction,
+        },
+    },
 };
-use reth_consensus_debug_client::{block_to_execution_payload_v3, EtherscanBlockProvider};
-use reth_rpc_layer::AuthClientService;
+use reth_rpc_layer::{AuthClientLayer, AuthClientService, JwtSecret};
 use reth_tracing::tracing::warn;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+/// Block sources
+pub mod source;
+pub use source::BlockSource;
+
+/// Engine API payloads
+pub mod payload;
+use payload::{EngineVersion, ForkSchedule, NewPayload, PayloadTransaction};
+
+/// Engine API status handling
+pub mod status;
+use status::{PayloadOutcome, StatusPolicy, RESUBMIT_DELAY};
+
+/// Reorg injection
+pub mod reorg;
+use reorg::MAX_REORG_DEPTH;
+
+/// Local block building
+pub mod devnet;
+use devnet::Devnet;
+
+/// Safe & finalized block policies
+pub mod forkchoice;
+use forkchoice::TagPolicy;
 
-/// A fake consensus layer that advances the chain on-demand by using therscan
-pub struct FakeCl {
-    // A [`EtherscanBlockProvider`].
-    pub etherscan: EtherscanBlockProvider,
+/// Negative testing with corrupted payloads
+pub mod mutation;
+use mutation::PayloadMutation;
+
+/// Differential testing against peer nodes
+pub mod differential;
+use differential::Peer;
+
+/// Engine API benchmarking
+pub mod bench;
+
+/// Engine API call recording & replay
+pub mod record;
+use record::Recorder;
+
+/// Shared block submission loop
+pub mod runner;
+
+/// Block prefetching
+mod prefetch;
+use prefetch::Prefetcher;
+
+/// A fake consensus layer that advances the chain on-demand by using a [`BlockSource`], or by
+/// asking the node to build blocks in [`Devnet`] mode.
+///
+/// `T` is the RPC transaction type of the chain, eg. [`OpTransaction`](payload::OpTransaction)
+/// for optimism.
+pub struct FakeCl<T = Transaction> {
+    /// Where blocks come from.
+    driver: Driver<T>,
+    /// Decides the engine API version of each block.
+    fork_schedule: ForkSchedule,
+    /// What to do on non `VALID` payload statuses.
+    status_policy: StatusPolicy,
+    /// Last submitted `VALID` blocks, used to build competing branches.
+    canonical: VecDeque<Block<T>>,
+    /// Blocks replaced by a reorg which was not switched back yet, from lowest to highest.
+    reorged_out: VecDeque<Block<T>>,
+    /// How the safe block is chosen.
+    safe_policy: TagPolicy,
+    /// How the finalized block is chosen.
+    finalized_policy: TagPolicy,
+    /// Hashes of the last submitted `VALID` blocks, used to resolve safe and finalized blocks.
+    submitted: BTreeMap<BlockNumber, BlockHash>,
+    /// Corrupted variants submitted before each block.
+    mutations: Vec<PayloadMutation>,
+    /// Nodes receiving the same payloads and forkchoice updates, for comparison.
+    peers: Vec<Peer>,
+    /// Records every engine API call issued to the driven node.
+    recorder: Option<Recorder>,
 }
 
-impl FakeCl {
-    /// Creates a [`Self`] from a [`Chain`] and `etherscan_url` if it exists.
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Creates a [`Self`] from any [`BlockSource`] and the [`ForkSchedule`] of the chain.
+    pub fn new(source: impl BlockSource<T> + 'static, fork_schedule: ForkSchedule) -> Self {
+        Self::with_driver(Driver::Source(Prefetcher::new(Arc::new(source))), fork_schedule)
+    }
+
+    /// Creates a [`Self`] that builds blocks locally with a [`Devnet`].
+    pub fn devnet(devnet: Devnet, fork_schedule: ForkSchedule) -> Self {
+        Self::with_driver(Driver::Devnet(devnet), fork_schedule)
+    }
+
+    fn with_driver(driver: Driver<T>, fork_schedule: ForkSchedule) -> Self {
+        Self {
+            driver,
+            fork_schedule,
+            status_policy: StatusPolicy::default(),
+            canonical: VecDeque::with_capacity(MAX_REORG_DEPTH),
+            reorged_out: VecDeque::new(),
+            safe_policy: TagPolicy::default(),
+            finalized_policy: TagPolicy::default(),
+            submitted: BTreeMap::new(),
+            mutations: Vec::new(),
+            peers: Vec::new(),
+            recorder: None,
+        }
+    }
+
+    /// Sets the [`StatusPolicy`] applied to non `VALID` payload statuses.
+    pub fn with_status_policy(mut self, status_policy: StatusPolicy) -> Self {
+        self.status_policy = status_policy;
+        self
+    }
+
+    /// Fetches up to `depth` blocks ahead of the one being submitted, without going past the
+    /// last known source tip. Has no effect in [`Devnet`] mode.
+    pub fn with_prefetch(mut self, depth: u64) -> Self {
+        if let Driver::Source(prefetcher) = &mut self.driver {
+            prefetcher.depth = depth;
+        }
+        self
+    }
+
+    /// Returns the latest block number known by the [`BlockSource`].
     ///
-    /// Requires an etherscan api key to be set as an environment variable.
-    pub fn new(chain: Chain, etherscan_url: Option<String>) -> Result<Self, eyre::Error> {
-        let etherscan_url = etherscan_url.map(Ok).unwrap_or_else(|| {
-            chain
-                .etherscan_urls()
-                .map(|urls| urls.0.to_string())
-                .ok_or_else(|| eyre::eyre!("failed to get etherscan url for chain: {chain}"))
-        })?;
-        let etherscan_api_key = chain.etherscan_api_key().ok_or_else(|| {
-            eyre::eyre!("etherscan api key not found for rpc consensus client for chain: {chain}")
-        })?;
-
-        Ok(Self { etherscan: EtherscanBlockProvider::new(etherscan_url, etherscan_api_key) })
+    /// A [`Devnet`] can always build the next block, so it has no tip.
+    pub async fn source_tip(&self) -> Result<BlockNumber, eyre::Error> {
+        match &self.driver {
+            Driver::Source(prefetcher) => prefetcher.latest().await,
+            Driver::Devnet(_) => Ok(BlockNumber::MAX),
+        }
     }
 
-    /// Advances the chain by querying `etherscan` for a specific block and issues a `newPayload` &
-    /// `FCU` request from that.
+    /// Returns the last submitted `VALID` block at `number`, if still tracked.
+    pub fn canonical_block(&self, number: BlockNumber) -> Option<&Block<T>> {
+        self.canonical.iter().rev().find(|block| block.header.number == number)
+    }
+
+    /// Advances the chain by querying the [`BlockSource`] for a specific block, or by building it
+    /// with the [`Devnet`], and issues a `newPayload` & `FCU` request from that.
+    ///
+    /// The engine API version of both requests follows the hardfork active at the block
+    /// timestamp. Non `VALID` statuses are handled according to the [`StatusPolicy`].
     pub async fn advance_chain<E: EngineTypes>(
         &mut self,
         auth_client: &HttpClient<AuthClientService<HttpBackend>>,
         block_number: u64,
         finalized_hash: BlockHash,
-    ) -> Result<(), eyre::Error> {
-        let etherscan_block = self.etherscan.load_block(block_number.into()).await?;
+    ) -> Result<PayloadOutcome, eyre::Error> {
+        let (payload, block) = match &mut self.driver {
+            Driver::Source(prefetcher) => {
+                let block = prefetcher.block(block_number).await?;
+                // The source chain goes on from the branch replaced by a reorg
+                if self
+                    .reorged_out
+                    .back()
+                    .is_some_and(|head| head.header.hash == block.header.parent_hash)
+                {
+                    self.restore_reorged_out();
+                }
+                if let Some(parent) = self
+                    .canonical
+                    .back()
+                    .filter(|parent| parent.header.hash == block.header.parent_hash)
+                {
+                    self.fork_schedule.validate_holocene_base_fee(&parent.header, &block.header)?;
+                }
+                let execution_requests = self.execution_requests(&block).await?;
+                let payload = NewPayload::from_block(
+                    block.clone(),
+                    &self.fork_schedule,
+                    execution_requests.clone(),
+                )?;
+                (payload, Some((block, execution_requests)))
+            }
+            Driver::Devnet(devnet) => {
+                let payload = devnet
+                    .build_payload(auth_client, block_number, &self.fork_schedule, finalized_hash)
+                    .await?;
+                (payload, None)
+            }
+        };
+
+        // Built blocks are not known before submitting them, so they are not corrupted
+        let mutations = match &block {
+            Some((block, execution_requests)) if !self.mutations.is_empty() => {
+                self.submit_mutations::<E>(auth_client, block, execution_requests).await?
+            }
+            _ => Vec::new(),
+        };
 
-        let payload = block_to_execution_payload_v3(etherscan_block);
         let block_hash = payload.block_hash();
+        let version = payload.version;
+        let state = self
+            .forkchoice_state(
+                BlockNumHash::new(block_number, block_hash),
+                block_number,
+                finalized_hash,
+            )
+            .await?;
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+
+            let started_at = Instant::now();
+            let new_payload_status =
+                self.submit_new_payload::<E>(auth_client, payload.clone()).await.inspect_err(|err| {
+                    warn!(target: "exex-consensus", %err, %block_hash, %block_number, ?version, "failed to submit new payload to execution client");
+                })?;
+            let new_payload_latency = started_at.elapsed();
+            let mut divergences =
+                self.compare_new_payload::<E>(&payload, &new_payload_status).await;
+
+            // A syncing node needs the forkchoice update to know which chain to sync to
+            let (forkchoice_updated_status, fcu_latency) = if new_payload_status.status.is_invalid()
+            {
+                (None, None)
+            } else {
+                let started_at = Instant::now();
+                let updated = self
+                    .submit_forkchoice_updated::<E>(auth_client, version, state, block_number)
+                    .await
+                    .inspect_err(|err| {
+                        warn!(target: "exex-consensus", %err, ?version, "failed to submit fork choice update to execution client");
+                    })?;
+                let latency = started_at.elapsed();
+                divergences.extend(
+                    self.compare_forkchoice_updated::<E>(
+                        version,
+                        state,
+                        block_number,
+                        &updated.payload_status,
+                    )
+                    .await,
+                );
+                (Some(updated.payload_status), Some(latency))
+            };
+
+            let outcome = PayloadOutcome {
+                block_number,
+                block_hash,
+                new_payload: new_payload_status,
+                forkchoice_updated: forkchoice_updated_status,
+                new_payload_latency,
+                forkchoice_updated_latency: fcu_latency,
+                gas_used: payload.payload.payload_inner.payload_inner.gas_used,
+                transaction_count: payload.payload.payload_inner.payload_inner.transactions.len()
+                    as u64,
+                attempts,
+                mutations: mutations.clone(),
+                divergences,
+            };
+
+            if outcome.is_valid() {
+                // Built blocks are only known by the node
+                let block = match block {
+                    Some((block, _)) => block,
+                    None => {
+                        auth_client
+                            .request("eth_getBlockByHash", rpc_params![block_hash, true])
+                            .await?
+                    }
+                };
+
+                if self.canonical.len() == MAX_REORG_DEPTH {
+                    self.canonical.pop_front();
+                }
+                self.canonical.push_back(block);
+                self.track_submitted(BlockNumHash::new(block_number, block_hash));
+                return Ok(outcome)
+            }
+
+            if !self.status_policy.should_resubmit(&outcome, attempts) {
+                return Ok(outcome)
+            }
+
+            warn!(target: "exex-consensus", ?outcome, "payload not valid, resubmitting");
+            tokio::time::sleep(RESUBMIT_DELAY).await;
+        }
+    }
+}
+
+/// Creates an engine API client for `url`, authenticated with the JWT secret at `jwt_secret`.
+pub fn auth_client(
+    url: &str,
+    jwt_secret: &Path,
+) -> Result<HttpClient<AuthClientService<HttpBackend>>, eyre::Error> {
+    let secret = JwtSecret::from_file(jwt_secret)?;
+    let middleware = tower::ServiceBuilder::default().layer(AuthClientLayer::new(secret));
+    Ok(HttpClientBuilder::default().set_http_middleware(middleware).build(url)?)
+}
+
+/// Where a [`FakeCl`] gets its blocks from.
+enum Driver<T> {
+    /// Follows an existing chain.
+    Source(Prefetcher<T>),
+    /// Builds a new chain.
+    Devnet(Devnet),
+}
+
+/// Issues the `newPayload` request matching the [`EngineVersion`] of the payload.
+async fn new_payload<E: EngineTypes>(
+    auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+    payload: NewPayload,
+) -> Result<PayloadStatus, eyre::Error> {
+    let NewPayload {
+        version,
+        payload,
+        versioned_hashes,
+        parent_beacon_block_root,
+        execution_requests,
+    } = payload;
+
+    let status = match version {
+        EngineVersion::V1 => {
+            EngineApiClient::<E>::new_payload_v1(auth_client, payload.payload_inner.payload_inner)
+                .await?
+        }
+        EngineVersion::V2 => {
+            EngineApiClient::<E>::new_payload_v2(
+                auth_client,
+                ExecutionPayloadInputV2 {
+                    execution_payload: payload.payload_inner.payload_inner,
+                    withdrawals: Some(payload.payload_inner.withdrawals),
+                },
+            )
+            .await?
+        }
+        EngineVersion::V3 => {
+            EngineApiClient::<E>::new_payload_v3(
+                auth_client,
+                payload,
+                versioned_hashes,
+                parent_beacon_block_root,
+            )
+            .await?
+        }
+        EngineVersion::V4 => {
+            EngineApiClient::<E>::new_payload_v4(
+                auth_client,
+                payload,
+                versioned_hashes,
+                parent_beacon_block_root,
+                execution_requests,
+            )
+            .await?
+        }
+    };
+
+    Ok(status)
+}
+
+/// Issues the `forkchoiceUpdated` request matching the [`EngineVersion`] of the head block.
+async fn forkchoice_updated<E: EngineTypes>(
+    auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+    version: EngineVersion,
+    state: ForkchoiceState,
+) -> Result<ForkchoiceUpdated, eyre::Error> {
+    let updated = match version {
+        EngineVersion::V1 => {
+            EngineApiClient::<E>::fork_choice_updated_v1(auth_client, state, None).await?
+        }
+        EngineVersion::V2 => {
+            EngineApiClient::<E>::fork_choice_updated_v2(auth_client, state, None).await?
+        }
+        EngineVersion::V3 | EngineVersion::V4 => {
+            EngineApiClient::<E>::fork_choice_updated_v3(auth_client, state, None).await?
+        }
+    };
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+    use jsonrpsee::{
+        server::{Server, ServerHandle},
+        types::ErrorObjectOwned,
+        RpcModule,
+    };
+    use reth::{chainspec::MAINNET, rpc::types::engine::PayloadStatusEnum};
+    use reth_node_ethereum::EthEngineTypes;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves empty blocks.
+    pub(crate) struct Empty;
+
+    impl BlockSource for Empty {
+        fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+            Box::pin(async { Ok(BlockNumber::MAX) })
+        }
+
+        fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+            Box::pin(async move {
+                let mut block = Block::<Transaction>::default();
+                block.header.inner.number = number;
+                block.header.hash = block.header.inner.hash_slow();
+                Ok(block)
+            })
+        }
+    }
+
+    /// Number of engine API calls received by the mock node.
+    #[derive(Default)]
+    struct Calls {
+        new_payload: AtomicUsize,
+        forkchoice_updated: AtomicUsize,
+    }
+
+    /// Serves a node answering `newPayload` with `new_payload` and `forkchoiceUpdated` with
+    /// `VALID`, and returns an engine API client querying it.
+    async fn engine(
+        new_payload: PayloadStatusEnum,
+    ) -> (HttpClient<AuthClientService<HttpBackend>>, Arc<Calls>, ServerHandle) {
+        let calls = Arc::new(Calls::default());
+        let mut module = RpcModule::new((calls.clone(), new_payload));
+        module
+            .register_method("engine_newPayloadV1", |_, (calls, status), _| {
+                calls.new_payload.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, ErrorObjectOwned>(PayloadStatus::from_status(status.clone()))
+            })
+            .unwrap();
+        module
+            .register_method("engine_forkchoiceUpdatedV1", |_, (calls, _), _| {
+                calls.forkchoice_updated.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, ErrorObjectOwned>(ForkchoiceUpdated::from_status(PayloadStatusEnum::Valid))
+            })
+            .unwrap();
+
+        let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let middleware =
+            tower::ServiceBuilder::default().layer(AuthClientLayer::new(JwtSecret::random()));
+        let client = HttpClientBuilder::default()
+            .set_http_middleware(middleware)
+            .build(format!("http://{addr}"))
+            .unwrap();
+        (client, calls, server.start(module))
+    }
+
+    #[tokio::test]
+    async fn syncing_payloads_are_followed_by_a_forkchoice_update() {
+        let (auth_client, calls, _server) = engine(PayloadStatusEnum::Syncing).await;
+        let mut fake_cl = FakeCl::new(Empty, ForkSchedule::new(MAINNET.as_ref()));
+
+        let outcome = fake_cl
+            .advance_chain::<EthEngineTypes>(&auth_client, 1, BlockHash::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(outcome.new_payload.status, PayloadStatusEnum::Syncing);
+        assert_eq!(
+            outcome.forkchoice_updated.map(|status| status.status),
+            Some(PayloadStatusEnum::Valid)
+        );
+        assert_eq!(calls.new_payload.load(Ordering::Relaxed), 1);
+        assert_eq!(calls.forkchoice_updated.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            fake_cl.canonical_block(1).map(|block| block.header.hash),
+            Some(outcome.block_hash)
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_payloads_are_not_followed_by_a_forkchoice_update() {
+        let invalid = PayloadStatusEnum::Invalid { validation_error: "bad state root".to_string() };
+        let (auth_client, calls, _server) = engine(invalid.clone()).await;
+        let mut fake_cl = FakeCl::new(Empty, ForkSchedule::new(MAINNET.as_ref()));
 
-        EngineApiClient::<E>::new_payload_v3(
-            auth_client,
-            payload.execution_payload_v3,
-            payload.versioned_hashes,
-            payload.parent_beacon_block_root,
-        )
-        .await
-        .inspect_err(|err|  {
-            warn!(target: "exex-consensus", %err, %block_hash,  %block_number, "failed to submit new payload to execution client");
-        })?;
-
-        EngineApiClient::<E>::fork_choice_updated_v3(
-            auth_client,
-            ForkchoiceState {
-                head_block_hash: block_hash,
-                safe_block_hash: finalized_hash,
-                finalized_block_hash: finalized_hash,
-            },
-            None,
-        )
-        .await
-        .inspect_err(|err| {
-            warn!(target: "exex-consensus", %err, "failed to submit fork
-                choice update to execution client");
-        })?;
-
-        Ok(())
+        let outcome = fake_cl
+            .advance_chain::<EthEngineTypes>(&auth_client, 1, BlockHash::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(outcome.new_payload.status, invalid);
+        assert!(outcome.forkchoice_updated.is_none());
+        assert_eq!(calls.forkchoice_updated.load(Ordering::Relaxed), 0);
+        assert!(fake_cl.canonical_block(1).is_none());
     }
 }
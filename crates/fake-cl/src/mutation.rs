@@ -0,0 +1,303 @@
+use crate::{
+    payload::{EngineVersion, ForkSchedule, NewPayload, PayloadTransaction},
+    FakeCl,
+};
+use alloy_eips::eip7685::Requests;
+use alloy_primitives::{BlockHash, B256};
+use jsonrpsee::http_client::{transport::HttpBackend, HttpClient};
+use reth::{
+    api::EngineTypes,
+    rpc::types::{engine::PayloadStatus, Block},
+};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::{info, warn};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Value written over the corrupted field.
+const CORRUPTED: B256 = B256::repeat_byte(0xee);
+
+/// A deliberate corruption of a valid payload, which the node must reject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadMutation {
+    /// Wrong state root, with a matching block hash. Rejected after execution.
+    StateRoot,
+    /// Block hash not matching the payload.
+    BlockHash,
+    /// Grandparent as parent, with a matching block hash.
+    Parent,
+    /// Extra blob versioned hash. Since [`EngineVersion::V3`].
+    VersionedHashes,
+    /// Wrong parent beacon block root, with a matching block hash. Since [`EngineVersion::V3`].
+    ParentBeaconBlockRoot,
+}
+
+impl FromStr for PayloadMutation {
+    type Err = String;
+
+    /// Parses `state-root`, `block-hash`, `parent`, `versioned-hashes` or
+    /// `parent-beacon-block-root`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "state-root" => Ok(Self::StateRoot),
+            "block-hash" => Ok(Self::BlockHash),
+            "parent" => Ok(Self::Parent),
+            "versioned-hashes" => Ok(Self::VersionedHashes),
+            "parent-beacon-block-root" => Ok(Self::ParentBeaconBlockRoot),
+            _ => Err(format!(
+                "invalid mutation {s}: expected state-root, block-hash, parent, versioned-hashes or parent-beacon-block-root"
+            )),
+        }
+    }
+}
+
+/// Result of submitting a corrupted payload through `newPayload`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MutationOutcome {
+    /// Applied corruption.
+    pub mutation: PayloadMutation,
+    /// Block hash of the corrupted payload.
+    pub block_hash: BlockHash,
+    /// Status returned by `newPayload`.
+    pub status: PayloadStatus,
+    /// `latestValidHash` required by the engine API spec: `null` for block hash and versioned
+    /// hashes mismatches, the parent otherwise.
+    pub expected_latest_valid_hash: Option<BlockHash>,
+}
+
+impl MutationOutcome {
+    /// Whether the node rejected the payload with the expected `latestValidHash`.
+    pub fn is_rejected(&self) -> bool {
+        self.status.status.is_invalid() &&
+            self.status.latest_valid_hash == self.expected_latest_valid_hash
+    }
+}
+
+impl PayloadMutation {
+    /// Returns the corrupted payload of `block` alongside the expected `latestValidHash`, or
+    /// `None` if the mutation does not apply to it.
+    fn apply<T: PayloadTransaction>(
+        self,
+        block: &Block<T>,
+        grandparent: Option<BlockHash>,
+        fork_schedule: &ForkSchedule,
+        execution_requests: &Requests,
+    ) -> Result<Option<(NewPayload, Option<BlockHash>)>, eyre::Error> {
+        let from_block = |block: Block<T>| {
+            NewPayload::from_block(block, fork_schedule, execution_requests.clone())
+        };
+        let parent_hash = block.header.parent_hash;
+        let version = fork_schedule.version_at(block.header.timestamp);
+
+        let mutated = match self {
+            Self::StateRoot => {
+                let block = rehashed(block, |header| header.state_root = CORRUPTED);
+                (from_block(block)?, Some(parent_hash))
+            }
+            Self::BlockHash => {
+                let mut payload = from_block(block.clone())?;
+                payload.payload.payload_inner.payload_inner.block_hash = CORRUPTED;
+                (payload, None)
+            }
+            Self::Parent => {
+                let Some(grandparent) = grandparent else { return Ok(None) };
+                let block = rehashed(block, |header| header.parent_hash = grandparent);
+                (from_block(block)?, Some(grandparent))
+            }
+            Self::VersionedHashes if version >= EngineVersion::V3 => {
+                let mut payload = from_block(block.clone())?;
+                payload.versioned_hashes.push(CORRUPTED);
+                (payload, None)
+            }
+            Self::ParentBeaconBlockRoot if version >= EngineVersion::V3 => {
+                let block =
+                    rehashed(block, |header| header.parent_beacon_block_root = Some(CORRUPTED));
+                (from_block(block)?, Some(parent_hash))
+            }
+            Self::VersionedHashes | Self::ParentBeaconBlockRoot => return Ok(None),
+        };
+
+        Ok(Some(mutated))
+    }
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Submits corrupted variants of every valid block before it, which the node must reject.
+    pub fn with_mutations(mut self, mutations: Vec<PayloadMutation>) -> Self {
+        self.mutations = mutations;
+        self
+    }
+
+    /// Submits every configured [`PayloadMutation`] of `block` through `newPayload`.
+    pub(crate) async fn submit_mutations<E: EngineTypes>(
+        &self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        block: &Block<T>,
+        execution_requests: &Requests,
+    ) -> Result<Vec<MutationOutcome>, eyre::Error> {
+        let grandparent = block
+            .header
+            .number
+            .checked_sub(2)
+            .and_then(|number| self.submitted.get(&number).copied());
+
+        let mut outcomes = Vec::with_capacity(self.mutations.len());
+        for &mutation in &self.mutations {
+            let Some((payload, expected_latest_valid_hash)) =
+                mutation.apply(block, grandparent, &self.fork_schedule, execution_requests)?
+            else {
+                continue
+            };
+
+            let block_hash = payload.block_hash();
+            let status = self.submit_new_payload::<E>(auth_client, payload).await?;
+            let outcome =
+                MutationOutcome { mutation, block_hash, status, expected_latest_valid_hash };
+
+            if outcome.is_rejected() {
+                info!(target: "exex-consensus", block_number = block.header.number, ?mutation, "Corrupted payload rejected");
+            } else {
+                warn!(target: "exex-consensus", ?outcome, "Corrupted payload not rejected as expected");
+            }
+            outcomes.push(outcome);
+        }
+
+        Ok(outcomes)
+    }
+}
+
+/// Returns a copy of `block` with its header modified by `f` and its block hash recomputed.
+fn rehashed<T: Clone>(block: &Block<T>, f: impl FnOnce(&mut alloy_consensus::Header)) -> Block<T> {
+    let mut block = block.clone();
+    f(&mut block.header.inner);
+    block.header.hash = block.header.inner.hash_slow();
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth::{
+        chainspec::MAINNET,
+        rpc::types::{engine::PayloadStatusEnum, Transaction},
+    };
+
+    /// Mainnet Shanghai and Cancun timestamps.
+    const SHANGHAI: u64 = 1681338455;
+    const CANCUN: u64 = 1710338135;
+
+    const PARENT: BlockHash = BlockHash::repeat_byte(0x01);
+    const GRANDPARENT: BlockHash = BlockHash::repeat_byte(0x02);
+
+    fn block(timestamp: u64) -> Block<Transaction> {
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.number = 100;
+        block.header.inner.timestamp = timestamp;
+        block.header.inner.parent_hash = PARENT;
+        block.header.inner.parent_beacon_block_root =
+            (timestamp >= CANCUN).then_some(B256::repeat_byte(0x03));
+        block.header.hash = block.header.inner.hash_slow();
+        block
+    }
+
+    fn apply(
+        mutation: PayloadMutation,
+        block: &Block<Transaction>,
+        grandparent: Option<BlockHash>,
+    ) -> Option<(NewPayload, Option<BlockHash>)> {
+        mutation
+            .apply(block, grandparent, &ForkSchedule::new(MAINNET.as_ref()), &Requests::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_mutations() {
+        assert_eq!("state-root".parse::<PayloadMutation>(), Ok(PayloadMutation::StateRoot));
+        assert_eq!("block-hash".parse::<PayloadMutation>(), Ok(PayloadMutation::BlockHash));
+        assert_eq!("parent".parse::<PayloadMutation>(), Ok(PayloadMutation::Parent));
+        assert_eq!(
+            "versioned-hashes".parse::<PayloadMutation>(),
+            Ok(PayloadMutation::VersionedHashes)
+        );
+        assert_eq!(
+            "parent-beacon-block-root".parse::<PayloadMutation>(),
+            Ok(PayloadMutation::ParentBeaconBlockRoot)
+        );
+        assert!("receipts-root".parse::<PayloadMutation>().is_err());
+    }
+
+    #[test]
+    fn corrupts_state_root_with_matching_hash() {
+        let block = block(CANCUN);
+        let (payload, latest_valid_hash) = apply(PayloadMutation::StateRoot, &block, None).unwrap();
+
+        assert_eq!(payload.payload.payload_inner.payload_inner.state_root, CORRUPTED);
+        assert_ne!(payload.block_hash(), block.header.hash);
+        assert_eq!(payload.block_hash(), {
+            let mut header = block.header.inner.clone();
+            header.state_root = CORRUPTED;
+            header.hash_slow()
+        });
+        assert_eq!(latest_valid_hash, Some(PARENT));
+    }
+
+    #[test]
+    fn corrupts_block_hash() {
+        let block = block(CANCUN);
+        let (payload, latest_valid_hash) = apply(PayloadMutation::BlockHash, &block, None).unwrap();
+
+        assert_eq!(payload.block_hash(), CORRUPTED);
+        assert_eq!(payload.payload.payload_inner.payload_inner.state_root, block.header.state_root);
+        assert_eq!(latest_valid_hash, None);
+    }
+
+    #[test]
+    fn reparents_on_grandparent() {
+        let block = block(SHANGHAI);
+        assert!(apply(PayloadMutation::Parent, &block, None).is_none());
+
+        let (payload, latest_valid_hash) =
+            apply(PayloadMutation::Parent, &block, Some(GRANDPARENT)).unwrap();
+        assert_eq!(payload.payload.payload_inner.payload_inner.parent_hash, GRANDPARENT);
+        assert_ne!(payload.block_hash(), block.header.hash);
+        assert_eq!(latest_valid_hash, Some(GRANDPARENT));
+    }
+
+    #[test]
+    fn corrupts_cancun_fields_since_cancun() {
+        let block = block(SHANGHAI);
+        assert!(apply(PayloadMutation::VersionedHashes, &block, None).is_none());
+        assert!(apply(PayloadMutation::ParentBeaconBlockRoot, &block, None).is_none());
+
+        let block = self::block(CANCUN);
+        let (payload, latest_valid_hash) =
+            apply(PayloadMutation::VersionedHashes, &block, None).unwrap();
+        assert_eq!(payload.versioned_hashes, vec![CORRUPTED]);
+        assert_eq!(payload.block_hash(), block.header.hash);
+        assert_eq!(latest_valid_hash, None);
+
+        let (payload, latest_valid_hash) =
+            apply(PayloadMutation::ParentBeaconBlockRoot, &block, None).unwrap();
+        assert_eq!(payload.parent_beacon_block_root, CORRUPTED);
+        assert_ne!(payload.block_hash(), block.header.hash);
+        assert_eq!(latest_valid_hash, Some(PARENT));
+    }
+
+    #[test]
+    fn requires_invalid_status_and_latest_valid_hash() {
+        let outcome = |status, latest_valid_hash| MutationOutcome {
+            mutation: PayloadMutation::StateRoot,
+            block_hash: CORRUPTED,
+            status: PayloadStatus::new(status, latest_valid_hash),
+            expected_latest_valid_hash: Some(PARENT),
+        };
+        let invalid = || PayloadStatusEnum::Invalid { validation_error: "bad state root".into() };
+
+        assert!(outcome(invalid(), Some(PARENT)).is_rejected());
+        assert!(!outcome(invalid(), None).is_rejected());
+        assert!(!outcome(invalid(), Some(GRANDPARENT)).is_rejected());
+        assert!(!outcome(PayloadStatusEnum::Syncing, None).is_rejected());
+        assert!(!outcome(PayloadStatusEnum::Valid, Some(PARENT)).is_rejected());
+    }
+}
@@ -0,0 +1,599 @@
+use crate::{Driver, FakeCl};
+use alloy_consensus::Transaction as _;
+use alloy_eips::{
+    eip1559::BaseFeeParams,
+    eip2718::Encodable2718,
+    eip6110::{DEPOSIT_REQUEST_TYPE, MAINNET_DEPOSIT_CONTRACT_ADDRESS},
+    eip7002::{WITHDRAWAL_REQUEST_PREDEPLOY_ADDRESS, WITHDRAWAL_REQUEST_TYPE},
+    eip7251::{CONSOLIDATION_REQUEST_PREDEPLOY_ADDRESS, CONSOLIDATION_REQUEST_TYPE},
+    eip7685::{Requests, EMPTY_REQUESTS_HASH},
+};
+use alloy_primitives::{b256, Address, BlockHash, BlockNumber, Bytes, Log, B256, U256};
+use reth::{
+    chainspec::{EthChainSpec, EthereumHardfork, EthereumHardforks, ForkCondition},
+    rpc::types::{
+        engine::{ExecutionPayloadV1, ExecutionPayloadV2, ExecutionPayloadV3},
+        Block, BlockTransactions, Header, Transaction,
+    },
+};
+use reth_optimism_forks::OptimismHardfork;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+
+pub use op_alloy_rpc_types::Transaction as OpTransaction;
+
+/// Length of the Holocene `extraData`: version (1), denominator (4), elasticity (4).
+const HOLOCENE_EXTRA_DATA_LEN: usize = 9;
+
+/// Topic of the `DepositEvent` emitted by the deposit contract.
+const DEPOSIT_EVENT_TOPIC: B256 =
+    b256!("649bbc62d0e31342afea4e5cd82d4049e7e1ee912fc0889aa790803be39038c5");
+
+/// Lengths of the `DepositEvent` fields: pubkey, withdrawal credentials, amount, signature and
+/// index.
+const DEPOSIT_FIELD_LENGTHS: [usize; 5] = [48, 32, 8, 96, 8];
+
+/// An RPC transaction which can be included in an execution payload.
+pub trait PayloadTransaction:
+    Serialize + DeserializeOwned + Clone + Debug + Send + Sync + Unpin + 'static
+{
+    /// Returns the EIP-2718 encoding of the transaction.
+    fn encoded(&self) -> Bytes;
+
+    /// Returns the blob versioned hashes of the transaction, if any.
+    fn versioned_hashes(&self) -> Vec<B256>;
+}
+
+impl PayloadTransaction for Transaction {
+    fn encoded(&self) -> Bytes {
+        self.inner.encoded_2718().into()
+    }
+
+    fn versioned_hashes(&self) -> Vec<B256> {
+        self.blob_versioned_hashes().unwrap_or_default().to_vec()
+    }
+}
+
+impl PayloadTransaction for OpTransaction {
+    fn encoded(&self) -> Bytes {
+        self.inner.inner.encoded_2718().into()
+    }
+
+    fn versioned_hashes(&self) -> Vec<B256> {
+        Vec::new()
+    }
+}
+
+/// Engine API version used for `newPayload` and `forkchoiceUpdated` calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EngineVersion {
+    /// Paris.
+    V1,
+    /// Shanghai.
+    V2,
+    /// Cancun.
+    V3,
+    /// Prague.
+    V4,
+}
+
+impl EngineVersion {
+    /// Returns the `forkchoiceUpdated` method of this version. Prague reuses the Cancun one.
+    pub const fn fcu_method(self) -> &'static str {
+        match self {
+            Self::V1 => "engine_forkchoiceUpdatedV1",
+            Self::V2 => "engine_forkchoiceUpdatedV2",
+            Self::V3 | Self::V4 => "engine_forkchoiceUpdatedV3",
+        }
+    }
+}
+
+/// Hardfork activations that decide which [`EngineVersion`] a block requires, and the contracts
+/// its execution requests come from.
+#[derive(Debug, Clone)]
+pub struct ForkSchedule {
+    shanghai: ForkCondition,
+    cancun: ForkCondition,
+    prague: ForkCondition,
+    /// Never active on non-optimism chains.
+    holocene: ForkCondition,
+    /// EIP-1559 parameters of the chain spec once Holocene is active, used when `extraData`
+    /// leaves them unset.
+    holocene_base_fee_params: BaseFeeParams,
+    /// Address of the deposit contract, whose logs are EIP-6110 deposit requests.
+    deposit_contract: Address,
+}
+
+impl ForkSchedule {
+    /// Creates a [`Self`] from the hardforks of a chain spec.
+    ///
+    /// On optimism chains, Canyon and Ecotone activate Shanghai and Cancun respectively.
+    pub fn new(chain_spec: &(impl EthChainSpec + EthereumHardforks)) -> Self {
+        let holocene = chain_spec.fork(OptimismHardfork::Holocene);
+        let holocene_timestamp = match holocene {
+            ForkCondition::Timestamp(timestamp) => timestamp,
+            _ => u64::MAX,
+        };
+        Self {
+            shanghai: chain_spec.fork(EthereumHardfork::Shanghai),
+            cancun: chain_spec.fork(EthereumHardfork::Cancun),
+            prague: chain_spec.fork(EthereumHardfork::Prague),
+            holocene,
+            holocene_base_fee_params: chain_spec.base_fee_params_at_timestamp(holocene_timestamp),
+            deposit_contract: chain_spec
+                .deposit_contract()
+                .map_or(MAINNET_DEPOSIT_CONTRACT_ADDRESS, |contract| contract.address),
+        }
+    }
+
+    /// Whether the optimism Holocene hardfork is active at `timestamp`.
+    pub fn is_holocene_active_at(&self, timestamp: u64) -> bool {
+        self.holocene.active_at_timestamp(timestamp)
+    }
+
+    /// Checks that the base fee of `header` follows the EIP-1559 parameters set by the Holocene
+    /// `extraData` of its `parent`, or by the chain spec when unset.
+    ///
+    /// Does nothing before Holocene is active at the parent.
+    pub fn validate_holocene_base_fee(
+        &self,
+        parent: &Header,
+        header: &Header,
+    ) -> Result<(), eyre::Error> {
+        if !self.is_holocene_active_at(parent.timestamp) {
+            return Ok(())
+        }
+
+        let (denominator, elasticity) = decode_holocene_extra_data(&parent.extra_data)
+            .map_err(|err| eyre::eyre!("block {} has invalid extraData: {err}", parent.number))?;
+        let params = if denominator == 0 {
+            self.holocene_base_fee_params
+        } else {
+            BaseFeeParams::new(denominator.into(), elasticity.into())
+        };
+
+        let expected = parent.next_block_base_fee(params);
+        if header.base_fee_per_gas != expected {
+            eyre::bail!(
+                "block {} has base fee {:?}, expected {expected:?} from the EIP-1559 parameters of its parent",
+                header.number,
+                header.base_fee_per_gas
+            )
+        }
+        Ok(())
+    }
+
+    /// Whether `header` commits to execution requests, which must then be derived with
+    /// [`Self::execution_requests`].
+    pub fn has_execution_requests(&self, header: &Header) -> bool {
+        self.version_at(header.timestamp) >= EngineVersion::V4 &&
+            header.requests_hash != Some(EMPTY_REQUESTS_HASH)
+    }
+
+    /// Returns the addresses of the contracts whose logs make up execution requests.
+    pub fn request_contracts(&self) -> Vec<Address> {
+        vec![
+            self.deposit_contract,
+            WITHDRAWAL_REQUEST_PREDEPLOY_ADDRESS,
+            CONSOLIDATION_REQUEST_PREDEPLOY_ADDRESS,
+        ]
+    }
+
+    /// Derives the execution requests of a block from its `logs`.
+    ///
+    /// Deposits are decoded from the deposit contract logs. Withdrawal and consolidation requests
+    /// are dequeued by system calls, which leave no trace in receipts: they are taken from the
+    /// logs of the EIP-7002 and EIP-7251 contracts, which hold the same data, so requests queued
+    /// by earlier blocks are missed. [`NewPayload::from_block`] catches those against the
+    /// `requestsHash` of the block.
+    pub fn execution_requests(&self, logs: &[Log]) -> Result<Requests, eyre::Error> {
+        let mut deposits = Vec::new();
+        let mut withdrawals = Vec::new();
+        let mut consolidations = Vec::new();
+        for log in logs {
+            if log.address == self.deposit_contract {
+                if log.topics().first() == Some(&DEPOSIT_EVENT_TOPIC) {
+                    deposits.extend(
+                        decode_deposit(&log.data.data)
+                            .map_err(|err| eyre::eyre!("invalid deposit log: {err}"))?,
+                    );
+                }
+            } else if log.address == WITHDRAWAL_REQUEST_PREDEPLOY_ADDRESS {
+                withdrawals.extend_from_slice(&log.data.data);
+            } else if log.address == CONSOLIDATION_REQUEST_PREDEPLOY_ADDRESS {
+                consolidations.extend_from_slice(&log.data.data);
+            }
+        }
+
+        // Request types without requests are left out of the `requestsHash`
+        Ok(Requests::new(
+            [
+                (DEPOSIT_REQUEST_TYPE, deposits),
+                (WITHDRAWAL_REQUEST_TYPE, withdrawals),
+                (CONSOLIDATION_REQUEST_TYPE, consolidations),
+            ]
+            .into_iter()
+            .filter(|(_, requests)| !requests.is_empty())
+            .map(|(request_type, requests)| [&[request_type][..], &requests[..]].concat().into())
+            .collect(),
+        ))
+    }
+
+    /// Returns the [`EngineVersion`] of the hardfork active at `timestamp`.
+    pub fn version_at(&self, timestamp: u64) -> EngineVersion {
+        if self.prague.active_at_timestamp(timestamp) {
+            EngineVersion::V4
+        } else if self.cancun.active_at_timestamp(timestamp) {
+            EngineVersion::V3
+        } else if self.shanghai.active_at_timestamp(timestamp) {
+            EngineVersion::V2
+        } else {
+            EngineVersion::V1
+        }
+    }
+}
+
+/// Everything required to issue a `newPayload` request of a given [`EngineVersion`].
+///
+/// Fields not introduced yet by `version` are left as default and are not sent.
+#[derive(Debug, Clone)]
+pub struct NewPayload {
+    /// Engine API version of the block.
+    pub version: EngineVersion,
+    /// Execution payload.
+    pub payload: ExecutionPayloadV3,
+    /// Blob versioned hashes of the payload transactions. Since [`EngineVersion::V3`].
+    pub versioned_hashes: Vec<B256>,
+    /// Parent beacon block root. Since [`EngineVersion::V3`].
+    pub parent_beacon_block_root: B256,
+    /// Execution layer requests. Since [`EngineVersion::V4`].
+    pub execution_requests: Requests,
+}
+
+impl NewPayload {
+    /// Converts a full RPC block into a [`NewPayload`], picking the engine version from the
+    /// [`ForkSchedule`].
+    ///
+    /// Execution requests can't be derived from an RPC block, so Prague blocks need the
+    /// `execution_requests` derived by [`ForkSchedule::execution_requests`] unless they have
+    /// none. They are checked against the `requestsHash` of the block, if set.
+    pub fn from_block<T: PayloadTransaction>(
+        block: Block<T>,
+        fork_schedule: &ForkSchedule,
+        execution_requests: Requests,
+    ) -> Result<Self, eyre::Error> {
+        let version = fork_schedule.version_at(block.header.timestamp);
+
+        if fork_schedule.is_holocene_active_at(block.header.timestamp) {
+            decode_holocene_extra_data(&block.header.extra_data).map_err(|err| {
+                eyre::eyre!("block {} has invalid extraData: {err}", block.header.number)
+            })?;
+        }
+
+        if let BlockTransactions::Hashes(hashes) = &block.transactions {
+            if !hashes.is_empty() {
+                eyre::bail!("block {} is missing its full transactions", block.header.number)
+            }
+        }
+
+        let execution_requests = match block.header.requests_hash {
+            _ if version < EngineVersion::V4 => Requests::default(),
+            Some(requests_hash) if execution_requests.requests_hash() != requests_hash => {
+                eyre::bail!(
+                    "block {} has requests hash {requests_hash}, but the execution requests derived from its logs hash to {}",
+                    block.header.number,
+                    execution_requests.requests_hash()
+                )
+            }
+            _ => execution_requests,
+        };
+
+        let versioned_hashes =
+            block.transactions.txns().flat_map(PayloadTransaction::versioned_hashes).collect();
+
+        let payload = ExecutionPayloadV3 {
+            payload_inner: ExecutionPayloadV2 {
+                payload_inner: ExecutionPayloadV1 {
+                    parent_hash: block.header.parent_hash,
+                    fee_recipient: block.header.beneficiary,
+                    state_root: block.header.state_root,
+                    receipts_root: block.header.receipts_root,
+                    logs_bloom: block.header.logs_bloom,
+                    prev_randao: block.header.mix_hash,
+                    block_number: block.header.number,
+                    gas_limit: block.header.gas_limit,
+                    gas_used: block.header.gas_used,
+                    timestamp: block.header.timestamp,
+                    extra_data: block.header.extra_data.clone(),
+                    base_fee_per_gas: U256::from(block.header.base_fee_per_gas.unwrap_or_default()),
+                    block_hash: block.header.hash,
+                    transactions: block
+                        .transactions
+                        .txns()
+                        .map(PayloadTransaction::encoded)
+                        .collect(),
+                },
+                withdrawals: block
+                    .withdrawals
+                    .map(|withdrawals| withdrawals.into_iter().collect())
+                    .unwrap_or_default(),
+            },
+            blob_gas_used: block.header.blob_gas_used.unwrap_or_default(),
+            excess_blob_gas: block.header.excess_blob_gas.unwrap_or_default(),
+        };
+
+        Ok(Self {
+            version,
+            payload,
+            versioned_hashes,
+            parent_beacon_block_root: block.header.parent_beacon_block_root.unwrap_or_default(),
+            execution_requests,
+        })
+    }
+
+    /// Returns the block hash of the payload.
+    pub fn block_hash(&self) -> BlockHash {
+        self.payload.payload_inner.payload_inner.block_hash
+    }
+
+    /// Returns the block number of the payload.
+    pub fn block_number(&self) -> BlockNumber {
+        self.payload.payload_inner.payload_inner.block_number
+    }
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Derives the execution requests of `block` from the logs of the
+    /// [`BlockSource`](crate::BlockSource), if its header commits to any.
+    pub(crate) async fn execution_requests(
+        &self,
+        block: &Block<T>,
+    ) -> Result<Requests, eyre::Error> {
+        if !self.fork_schedule.has_execution_requests(&block.header) {
+            return Ok(Requests::default())
+        }
+        let Driver::Source(prefetcher) = &self.driver else {
+            eyre::bail!(
+                "block {} has execution requests, which can only be derived from a block source",
+                block.header.number
+            )
+        };
+
+        let logs = prefetcher
+            .source()
+            .logs(block.header.number, self.fork_schedule.request_contracts())
+            .await?;
+        self.fork_schedule.execution_requests(&logs)
+    }
+}
+
+/// Decodes the ABI encoded `DepositEvent` data of a deposit log into the fields of an EIP-6110
+/// deposit request, with the same layout.
+fn decode_deposit(data: &[u8]) -> Result<Vec<u8>, String> {
+    // Offsets and lengths never exceed the data, so adding them up can't overflow
+    let word = |offset: usize| -> Result<usize, String> {
+        let word = data.get(offset..offset + 32).ok_or("truncated data")?;
+        U256::from_be_slice(word)
+            .try_into()
+            .ok()
+            .filter(|value| *value <= data.len())
+            .ok_or_else(|| format!("offset or length at {offset} is out of bounds"))
+    };
+
+    let mut request = Vec::with_capacity(DEPOSIT_FIELD_LENGTHS.iter().sum());
+    for (field, expected) in DEPOSIT_FIELD_LENGTHS.into_iter().enumerate() {
+        let offset = word(field * 32)?;
+        let len = word(offset)?;
+        if len != expected {
+            return Err(format!("field {field} has {len} bytes, expected {expected}"))
+        }
+        request
+            .extend_from_slice(data.get(offset + 32..offset + 32 + len).ok_or("truncated data")?);
+    }
+    Ok(request)
+}
+
+/// Decodes the Holocene `extraData`, which carries the EIP-1559 denominator and elasticity used by
+/// the next block. Both are zero to use the ones of the chain spec.
+fn decode_holocene_extra_data(extra_data: &[u8]) -> Result<(u32, u32), String> {
+    if extra_data.len() != HOLOCENE_EXTRA_DATA_LEN {
+        return Err(format!("expected {HOLOCENE_EXTRA_DATA_LEN} bytes, got {}", extra_data.len()))
+    }
+    if extra_data[0] != 0 {
+        return Err(format!("unsupported version {}", extra_data[0]))
+    }
+
+    let denominator = u32::from_be_bytes(extra_data[1..5].try_into().expect("4 bytes"));
+    let elasticity = u32::from_be_bytes(extra_data[5..9].try_into().expect("4 bytes"));
+    if denominator == 0 && elasticity != 0 {
+        return Err("zero denominator with non-zero elasticity".to_string())
+    }
+
+    Ok((denominator, elasticity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth::chainspec::MAINNET;
+
+    /// Shanghai, Cancun and Prague activate at 10, 20 and 30, Holocene at 40.
+    fn schedule() -> ForkSchedule {
+        ForkSchedule {
+            shanghai: ForkCondition::Timestamp(10),
+            cancun: ForkCondition::Timestamp(20),
+            prague: ForkCondition::Timestamp(30),
+            holocene: ForkCondition::Timestamp(40),
+            holocene_base_fee_params: BaseFeeParams::new(250, 6),
+            deposit_contract: MAINNET_DEPOSIT_CONTRACT_ADDRESS,
+        }
+    }
+
+    #[test]
+    fn versions_follow_active_hardfork() {
+        let schedule = schedule();
+
+        assert_eq!(schedule.version_at(0), EngineVersion::V1);
+        assert_eq!(schedule.version_at(9), EngineVersion::V1);
+        assert_eq!(schedule.version_at(10), EngineVersion::V2);
+        assert_eq!(schedule.version_at(20), EngineVersion::V3);
+        assert_eq!(schedule.version_at(30), EngineVersion::V4);
+        assert_eq!(schedule.version_at(u64::MAX), EngineVersion::V4);
+    }
+
+    #[test]
+    fn versions_follow_mainnet() {
+        let schedule = ForkSchedule::new(MAINNET.as_ref());
+
+        assert_eq!(schedule.version_at(1681338454), EngineVersion::V1);
+        assert_eq!(schedule.version_at(1681338455), EngineVersion::V2);
+        assert_eq!(schedule.version_at(1710338135), EngineVersion::V3);
+        assert!(!schedule.is_holocene_active_at(u64::MAX));
+    }
+
+    /// Returns Holocene `extraData` with the given EIP-1559 parameters.
+    fn holocene_extra_data(denominator: u32, elasticity: u32) -> Bytes {
+        [&[0][..], &denominator.to_be_bytes(), &elasticity.to_be_bytes()].concat().into()
+    }
+
+    /// Returns a Holocene header at `number` with a base fee of 1 gwei, using half its gas.
+    fn holocene_header(number: u64, extra_data: Bytes) -> Header {
+        let mut header = Header::default();
+        header.inner.number = number;
+        header.inner.timestamp = 40 + number;
+        header.inner.gas_limit = 30_000_000;
+        header.inner.gas_used = 15_000_000 + number;
+        header.inner.base_fee_per_gas = Some(1_000_000_000);
+        header.inner.extra_data = extra_data;
+        header
+    }
+
+    #[test]
+    fn decodes_holocene_extra_data() {
+        assert_eq!(decode_holocene_extra_data(&holocene_extra_data(250, 6)), Ok((250, 6)));
+        assert_eq!(decode_holocene_extra_data(&holocene_extra_data(0, 0)), Ok((0, 0)));
+
+        assert!(decode_holocene_extra_data(&[]).is_err());
+        assert!(decode_holocene_extra_data(&holocene_extra_data(250, 6)[..8]).is_err());
+        assert!(decode_holocene_extra_data(&holocene_extra_data(0, 6)).is_err());
+        let mut versioned = holocene_extra_data(250, 6).to_vec();
+        versioned[0] = 1;
+        assert!(decode_holocene_extra_data(&versioned).is_err());
+    }
+
+    #[test]
+    fn checks_holocene_base_fee_against_parent_parameters() {
+        let schedule = schedule();
+        let parent = holocene_header(1, holocene_extra_data(50, 2));
+
+        let mut header = holocene_header(2, holocene_extra_data(50, 2));
+        header.inner.base_fee_per_gas = parent.next_block_base_fee(BaseFeeParams::new(50, 2));
+        schedule.validate_holocene_base_fee(&parent, &header).unwrap();
+
+        // Computed with the chain spec parameters instead of the parent ones
+        header.inner.base_fee_per_gas = parent.next_block_base_fee(BaseFeeParams::new(250, 6));
+        assert!(schedule.validate_holocene_base_fee(&parent, &header).is_err());
+    }
+
+    #[test]
+    fn falls_back_to_chain_spec_parameters() {
+        let schedule = schedule();
+        let parent = holocene_header(1, holocene_extra_data(0, 0));
+
+        let mut header = holocene_header(2, holocene_extra_data(0, 0));
+        header.inner.base_fee_per_gas = parent.next_block_base_fee(BaseFeeParams::new(250, 6));
+        schedule.validate_holocene_base_fee(&parent, &header).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_holocene_blocks() {
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.timestamp = 40;
+        block.header.inner.extra_data = Bytes::from_static(b"fake-cl reorg");
+
+        let err = NewPayload::from_block(block, &schedule(), Requests::default()).unwrap_err();
+        assert!(err.to_string().contains("invalid extraData"));
+    }
+
+    #[test]
+    fn prague_reuses_cancun_forkchoice_updated() {
+        assert_eq!(EngineVersion::V1.fcu_method(), "engine_forkchoiceUpdatedV1");
+        assert_eq!(EngineVersion::V2.fcu_method(), "engine_forkchoiceUpdatedV2");
+        assert_eq!(EngineVersion::V3.fcu_method(), "engine_forkchoiceUpdatedV3");
+        assert_eq!(EngineVersion::V4.fcu_method(), "engine_forkchoiceUpdatedV3");
+    }
+
+    #[test]
+    fn payload_version_follows_block_timestamp() {
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.timestamp = 15;
+
+        let payload = NewPayload::from_block(block, &schedule(), Requests::default()).unwrap();
+        assert_eq!(payload.version, EngineVersion::V2);
+    }
+
+    /// Returns the ABI encoded `DepositEvent` data of a deposit whose fields are filled with
+    /// `byte`.
+    fn deposit_data(byte: u8) -> Vec<u8> {
+        let mut head = Vec::new();
+        let mut tail = Vec::new();
+        for len in DEPOSIT_FIELD_LENGTHS {
+            let offset = DEPOSIT_FIELD_LENGTHS.len() * 32 + tail.len();
+            head.extend_from_slice(&U256::from(offset).to_be_bytes::<32>());
+            tail.extend_from_slice(&U256::from(len).to_be_bytes::<32>());
+            tail.extend(std::iter::repeat(byte).take(len));
+            tail.resize(tail.len().next_multiple_of(32), 0);
+        }
+        [head, tail].concat()
+    }
+
+    #[test]
+    fn derives_execution_requests_from_logs() {
+        let schedule = schedule();
+        let withdrawal = vec![0x02; 76];
+        let logs = [
+            Log::new_unchecked(
+                MAINNET_DEPOSIT_CONTRACT_ADDRESS,
+                vec![DEPOSIT_EVENT_TOPIC],
+                deposit_data(0x01).into(),
+            ),
+            Log::new_unchecked(Address::repeat_byte(0x03), Vec::new(), Bytes::new()),
+            Log::new_unchecked(
+                WITHDRAWAL_REQUEST_PREDEPLOY_ADDRESS,
+                Vec::new(),
+                withdrawal.clone().into(),
+            ),
+        ];
+
+        let requests = schedule.execution_requests(&logs).unwrap();
+        let deposit = [&[DEPOSIT_REQUEST_TYPE][..], &[0x01; 192]].concat();
+        let withdrawal = [&[WITHDRAWAL_REQUEST_TYPE][..], &withdrawal].concat();
+        assert_eq!(requests, Requests::new(vec![deposit.into(), withdrawal.into()]));
+
+        assert_eq!(schedule.execution_requests(&[]).unwrap().requests_hash(), EMPTY_REQUESTS_HASH);
+        assert!(decode_deposit(&deposit_data(0x01)[..500]).is_err());
+    }
+
+    #[test]
+    fn checks_execution_requests_against_requests_hash() {
+        let schedule = schedule();
+        let logs = [Log::new_unchecked(
+            MAINNET_DEPOSIT_CONTRACT_ADDRESS,
+            vec![DEPOSIT_EVENT_TOPIC],
+            deposit_data(0x01).into(),
+        )];
+        let requests = schedule.execution_requests(&logs).unwrap();
+
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.timestamp = 30;
+        block.header.inner.requests_hash = Some(requests.requests_hash());
+        assert!(schedule.has_execution_requests(&block.header));
+
+        let payload = NewPayload::from_block(block.clone(), &schedule, requests.clone()).unwrap();
+        assert_eq!(payload.version, EngineVersion::V4);
+        assert_eq!(payload.execution_requests, requests);
+
+        let err = NewPayload::from_block(block, &schedule, Requests::default()).unwrap_err();
+        assert!(err.to_string().contains("derived from its logs"), "{err}");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{payload::PayloadTransaction, BlockSource};
+use alloy_eips::BlockNumHash;
+use alloy_primitives::{BlockHash, BlockNumber};
+use reth::rpc::types::Block;
+use reth_tracing::tracing::warn;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::task::JoinHandle;
+
+/// Fetches the blocks following the one being submitted, with up to `depth` requests in flight.
+///
+/// Blocks are still handed out strictly in order, one at a time, and each must extend the
+/// previous one. Prefetched blocks that don't, eg. after a reorg of the source, are fetched again.
+pub(crate) struct Prefetcher<T> {
+    source: Arc<dyn BlockSource<T>>,
+    /// Number of blocks fetched ahead. Zero disables prefetching.
+    pub(crate) depth: u64,
+    /// Latest block number known by the source. Prefetching never goes past it.
+    tip: AtomicU64,
+    /// In flight requests of consecutive blocks, from lowest to highest.
+    pending: VecDeque<(BlockNumber, JoinHandle<eyre::Result<Block<T>>>)>,
+    /// Last block handed out.
+    last: Option<BlockNumHash>,
+}
+
+impl<T: PayloadTransaction> Prefetcher<T> {
+    /// Creates a [`Self`] with prefetching disabled.
+    pub(crate) fn new(source: Arc<dyn BlockSource<T>>) -> Self {
+        Self { source, depth: 0, tip: AtomicU64::new(0), pending: VecDeque::new(), last: None }
+    }
+
+    /// Returns the underlying [`BlockSource`].
+    pub(crate) fn source(&self) -> &dyn BlockSource<T> {
+        self.source.as_ref()
+    }
+
+    /// Returns the latest block number known by the source.
+    pub(crate) async fn latest(&self) -> Result<BlockNumber, eyre::Error> {
+        let tip = self.source.latest().await?;
+        self.tip.store(tip, Ordering::Relaxed);
+        Ok(tip)
+    }
+
+    /// Returns block `number`, and starts fetching the next `depth` blocks.
+    pub(crate) async fn block(&mut self, number: BlockNumber) -> Result<Block<T>, eyre::Error> {
+        // Drops requests that do not lead to `number`, eg. after a restart from another height
+        while self.pending.front().is_some_and(|(pending, _)| *pending < number) {
+            self.pending.pop_front().expect("not empty").1.abort();
+        }
+        if self.pending.front().is_some_and(|(pending, _)| *pending != number) {
+            self.abort_all();
+        }
+
+        let prefetched = match self.pending.pop_front() {
+            Some((_, handle)) => Some(handle.await?),
+            None => None,
+        };
+        self.fill(number);
+
+        let mut block = match prefetched {
+            Some(Ok(block)) => block,
+            // A failed prefetch may succeed now, since the source might have caught up
+            _ => self.source.block(number).await?,
+        };
+
+        if let Some(parent) = self.unlinked_parent(&block) {
+            warn!(number, %parent, parent_hash = %block.header.parent_hash, "Prefetched block does not extend the previous one, refetching");
+            self.abort_all();
+            block = self.source.block(number).await?;
+            if let Some(parent) = self.unlinked_parent(&block) {
+                eyre::bail!(
+                    "block {number} of the source has parent {}, expected {parent}: the source chain reorged",
+                    block.header.parent_hash
+                )
+            }
+            self.fill(number);
+        }
+
+        self.last = Some(BlockNumHash::new(number, block.header.hash));
+        Ok(block)
+    }
+
+    /// Returns the hash of the previous block if `block` should, but does not, extend it.
+    fn unlinked_parent(&self, block: &Block<T>) -> Option<BlockHash> {
+        self.last
+            .filter(|last| {
+                last.number + 1 == block.header.number && last.hash != block.header.parent_hash
+            })
+            .map(|last| last.hash)
+    }
+
+    /// Queues requests for the blocks after `number`, up to `depth` blocks ahead.
+    fn fill(&mut self, number: BlockNumber) {
+        let next = self.pending.back().map_or(number + 1, |(pending, _)| pending + 1);
+        let last = (number + self.depth).min(self.tip.load(Ordering::Relaxed));
+
+        for block_number in next..=last {
+            let source = self.source.clone();
+            let handle = tokio::spawn(async move { source.block(block_number).await });
+            self.pending.push_back((block_number, handle));
+        }
+    }
+
+    /// Cancels every in flight request.
+    fn abort_all(&mut self) {
+        for (_, handle) in self.pending.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+impl<T> Drop for Prefetcher<T> {
+    fn drop(&mut self) {
+        for (_, handle) in &self.pending {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+    use reth::rpc::types::Transaction;
+    use std::{
+        collections::{BTreeMap, HashSet},
+        sync::{atomic::AtomicUsize, Mutex},
+    };
+    use tokio::sync::Notify;
+
+    /// A chain whose blocks can be replaced, failing once for the given block numbers.
+    #[derive(Default)]
+    struct Chain {
+        blocks: Mutex<BTreeMap<BlockNumber, Block>>,
+        fail_once: Mutex<HashSet<BlockNumber>>,
+        /// Number of answered block requests.
+        answered: AtomicUsize,
+        /// Notified on every answered block request.
+        answers: Notify,
+    }
+
+    impl Chain {
+        /// Replaces blocks `from..=to` with a branch on top of block `from - 1`, tagged `branch`.
+        fn fork(&self, from: BlockNumber, to: BlockNumber, branch: u8) {
+            let mut blocks = self.blocks.lock().unwrap();
+            let mut parent_hash = blocks.get(&(from - 1)).map(|block| block.header.hash);
+            for number in from..=to {
+                let mut block = Block::<Transaction>::default();
+                block.header.number = number;
+                block.header.inner.parent_hash = parent_hash.unwrap_or_default();
+                block.header.hash = BlockHash::with_last_byte(branch) ^
+                    BlockHash::left_padding_from(&number.to_be_bytes());
+                parent_hash = Some(block.header.hash);
+                blocks.insert(number, block);
+            }
+        }
+
+        /// Waits until `count` block requests were answered.
+        async fn answered(&self, count: usize) {
+            loop {
+                let answer = self.answers.notified();
+                if self.answered.load(Ordering::SeqCst) >= count {
+                    return
+                }
+                answer.await;
+            }
+        }
+    }
+
+    impl BlockSource for Chain {
+        fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+            Box::pin(async move { Ok(*self.blocks.lock().unwrap().keys().last().unwrap_or(&0)) })
+        }
+
+        fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<Transaction>>> {
+            Box::pin(async move {
+                let block = if self.fail_once.lock().unwrap().remove(&number) {
+                    Err(eyre::eyre!("block {number} failed"))
+                } else {
+                    self.blocks
+                        .lock()
+                        .unwrap()
+                        .get(&number)
+                        .cloned()
+                        .ok_or_else(|| eyre::eyre!("not found"))
+                };
+                self.answered.fetch_add(1, Ordering::SeqCst);
+                self.answers.notify_waiters();
+                block
+            })
+        }
+    }
+
+    fn prefetcher(chain: &Arc<Chain>, depth: u64) -> Prefetcher<Transaction> {
+        let mut prefetcher = Prefetcher::new(chain.clone() as Arc<dyn BlockSource>);
+        prefetcher.depth = depth;
+        prefetcher
+    }
+
+    #[tokio::test]
+    async fn hands_out_blocks_in_order() {
+        let chain = Arc::new(Chain::default());
+        chain.fork(1, 5, 1);
+        let mut prefetcher = prefetcher(&chain, 2);
+        prefetcher.latest().await.unwrap();
+
+        for number in 1..=5 {
+            assert_eq!(prefetcher.block(number).await.unwrap().header.number, number);
+        }
+    }
+
+    #[tokio::test]
+    async fn refetches_stale_prefetched_blocks() {
+        let chain = Arc::new(Chain::default());
+        chain.fork(1, 3, 1);
+        chain.fail_once.lock().unwrap().insert(2);
+        let mut prefetcher = prefetcher(&chain, 2);
+        prefetcher.latest().await.unwrap();
+
+        prefetcher.block(1).await.unwrap();
+        // Lets the prefetches of blocks 2 and 3 complete before the source reorgs
+        chain.answered(3).await;
+        chain.fork(2, 3, 2);
+
+        let block = prefetcher.block(2).await.unwrap();
+        assert_eq!(prefetcher.block(3).await.unwrap().header.parent_hash, block.header.hash);
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_source_reorged_submitted_blocks() {
+        let chain = Arc::new(Chain::default());
+        chain.fork(1, 3, 1);
+        let mut prefetcher = prefetcher(&chain, 0);
+
+        prefetcher.block(1).await.unwrap();
+        prefetcher.block(2).await.unwrap();
+        chain.fork(2, 3, 2);
+
+        assert!(prefetcher.block(3).await.unwrap_err().to_string().contains("reorged"));
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{
+    forkchoice_updated, new_payload,
+    payload::{EngineVersion, NewPayload, PayloadTransaction},
+    FakeCl,
+};
+use alloy_primitives::BlockNumber;
+use jsonrpsee::{
+    core::{client::ClientT, params::ArrayParams},
+    http_client::{transport::HttpBackend, HttpClient},
+};
+use reth::{
+    api::EngineTypes,
+    rpc::types::engine::{
+        ExecutionPayloadInputV2, ForkchoiceState, ForkchoiceUpdated, PayloadStatus,
+    },
+};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// An engine API request and its response, as one line of a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCall {
+    /// Block number of the payload or head.
+    pub block_number: BlockNumber,
+    /// Engine API method, eg. `engine_newPayloadV3`.
+    pub method: String,
+    /// JSON-RPC params, sent as is when replaying.
+    pub params: Value,
+    /// Returned status, if the request succeeded.
+    pub status: Option<PayloadStatus>,
+    /// Error of the request, if it failed.
+    pub error: Option<String>,
+    /// Time taken by the request.
+    pub latency: Duration,
+}
+
+/// Writes every engine API call issued by a [`FakeCl`] to a JSONL file.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    /// Creates (or truncates) the recording at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, eyre::Error> {
+        Ok(Self { writer: Mutex::new(BufWriter::new(File::create(path)?)) })
+    }
+
+    /// Appends `call`, flushing it right away so the recording survives crashes.
+    fn record(&self, call: &RecordedCall) -> Result<(), eyre::Error> {
+        let mut writer = self.writer.lock().expect("not poisoned");
+        serde_json::to_writer(&mut *writer, call)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Records every engine API call issued to the driven node with `recorder`.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Issues a `newPayload` request, recording it if enabled.
+    pub(crate) async fn submit_new_payload<E: EngineTypes>(
+        &self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        payload: NewPayload,
+    ) -> Result<PayloadStatus, eyre::Error> {
+        let Some(recorder) = &self.recorder else {
+            return new_payload::<E>(auth_client, payload).await
+        };
+
+        let block_number = payload.block_number();
+        let (method, params) = new_payload_request(&payload);
+        let started_at = Instant::now();
+        let result = new_payload::<E>(auth_client, payload).await;
+
+        recorder.record(&RecordedCall {
+            block_number,
+            method: method.to_string(),
+            params,
+            status: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(ToString::to_string),
+            latency: started_at.elapsed(),
+        })?;
+        result
+    }
+
+    /// Issues a `forkchoiceUpdated` request, recording it if enabled.
+    pub(crate) async fn submit_forkchoice_updated<E: EngineTypes>(
+        &self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        version: EngineVersion,
+        state: ForkchoiceState,
+        head_number: BlockNumber,
+    ) -> Result<ForkchoiceUpdated, eyre::Error> {
+        let Some(recorder) = &self.recorder else {
+            return forkchoice_updated::<E>(auth_client, version, state).await
+        };
+
+        let method = version.fcu_method();
+        let started_at = Instant::now();
+        let result = forkchoice_updated::<E>(auth_client, version, state).await;
+
+        recorder.record(&RecordedCall {
+            block_number: head_number,
+            method: method.to_string(),
+            params: json!([state, null]),
+            status: result.as_ref().ok().map(|updated| updated.payload_status.clone()),
+            error: result.as_ref().err().map(ToString::to_string),
+            latency: started_at.elapsed(),
+        })?;
+        result
+    }
+}
+
+/// Returns the method and JSON-RPC params of the `newPayload` request of `payload`.
+fn new_payload_request(payload: &NewPayload) -> (&'static str, Value) {
+    let NewPayload {
+        version,
+        payload,
+        versioned_hashes,
+        parent_beacon_block_root,
+        execution_requests,
+    } = payload;
+
+    match version {
+        EngineVersion::V1 => ("engine_newPayloadV1", json!([payload.payload_inner.payload_inner])),
+        EngineVersion::V2 => (
+            "engine_newPayloadV2",
+            json!([ExecutionPayloadInputV2 {
+                execution_payload: payload.payload_inner.payload_inner.clone(),
+                withdrawals: Some(payload.payload_inner.withdrawals.clone()),
+            }]),
+        ),
+        EngineVersion::V3 => {
+            ("engine_newPayloadV3", json!([payload, versioned_hashes, parent_beacon_block_root]))
+        }
+        EngineVersion::V4 => (
+            "engine_newPayloadV4",
+            json!([payload, versioned_hashes, parent_beacon_block_root, execution_requests]),
+        ),
+    }
+}
+
+/// A replayed call whose status differs from the recording.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayMismatch {
+    /// Recorded call.
+    pub recorded: RecordedCall,
+    /// Status returned when replaying, if the request succeeded.
+    pub status: Option<PayloadStatus>,
+    /// Error of the replayed request, if it failed.
+    pub error: Option<String>,
+}
+
+/// Sends every call of the recording at `path` to `auth_client`, in order, and returns those whose
+/// status differs from the recorded one.
+pub async fn replay(
+    auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+    path: impl AsRef<Path>,
+) -> Result<Vec<ReplayMismatch>, eyre::Error> {
+    let mut mismatches = Vec::new();
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let recorded: RecordedCall = serde_json::from_str(&line)?;
+
+        let mut params = ArrayParams::new();
+        for param in recorded.params.as_array().into_iter().flatten() {
+            params.insert(param)?;
+        }
+        let response = auth_client.request::<Value, _>(&recorded.method, params).await;
+
+        // `forkchoiceUpdated` wraps its status
+        let (status, error) = match response {
+            Ok(response) if recorded.method.starts_with("engine_forkchoiceUpdated") => {
+                (Some(serde_json::from_value::<ForkchoiceUpdated>(response)?.payload_status), None)
+            }
+            Ok(response) => (Some(serde_json::from_value::<PayloadStatus>(response)?), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        if status == recorded.status && error.is_some() == recorded.error.is_some() {
+            info!(target: "exex-consensus", block_number = recorded.block_number, method = %recorded.method, "Replayed call");
+        } else {
+            warn!(target: "exex-consensus", block_number = recorded.block_number, method = %recorded.method, ?status, ?error, expected = ?recorded.status, "Replayed call diverged");
+            mismatches.push(ReplayMismatch { recorded, status, error });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::ForkSchedule;
+    use alloy_eips::eip7685::Requests;
+    use reth::{
+        chainspec::MAINNET,
+        rpc::types::{engine::PayloadStatusEnum, Block, Transaction},
+    };
+
+    fn payload(version: EngineVersion) -> NewPayload {
+        let schedule = ForkSchedule::new(MAINNET.as_ref());
+        let mut payload =
+            NewPayload::from_block(Block::<Transaction>::default(), &schedule, Requests::default())
+                .unwrap();
+        payload.version = version;
+        payload
+    }
+
+    #[test]
+    fn builds_new_payload_request_of_each_version() {
+        for (version, method, params) in [
+            (EngineVersion::V1, "engine_newPayloadV1", 1),
+            (EngineVersion::V2, "engine_newPayloadV2", 1),
+            (EngineVersion::V3, "engine_newPayloadV3", 3),
+            (EngineVersion::V4, "engine_newPayloadV4", 4),
+        ] {
+            let request = new_payload_request(&payload(version));
+            assert_eq!(request.0, method);
+            assert_eq!(request.1.as_array().map(Vec::len), Some(params), "{method}");
+        }
+
+        // withdrawals are only sent since V2
+        let (_, params) = new_payload_request(&payload(EngineVersion::V1));
+        assert!(params[0].get("withdrawals").is_none());
+        let (_, params) = new_payload_request(&payload(EngineVersion::V2));
+        assert!(params[0].get("withdrawals").is_some());
+    }
+
+    #[test]
+    fn records_one_call_per_line() {
+        let path =
+            std::env::temp_dir().join(format!("fake-cl-{}-record.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+
+        let (method, params) = new_payload_request(&payload(EngineVersion::V3));
+        let calls = [
+            RecordedCall {
+                block_number: 1,
+                method: method.to_string(),
+                params,
+                status: Some(PayloadStatus::from_status(PayloadStatusEnum::Valid)),
+                error: None,
+                latency: Duration::from_millis(5),
+            },
+            RecordedCall {
+                block_number: 1,
+                method: EngineVersion::V3.fcu_method().to_string(),
+                params: json!([ForkchoiceState::default(), null]),
+                status: None,
+                error: Some("connection refused".to_string()),
+                latency: Duration::from_millis(1),
+            },
+        ];
+        for call in &calls {
+            recorder.record(call).unwrap();
+        }
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let recorded = lines
+            .lines()
+            .map(|line| serde_json::from_str::<RecordedCall>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(recorded.len(), 2);
+        for (recorded, call) in recorded.iter().zip(&calls) {
+            assert_eq!(recorded.method, call.method);
+            assert_eq!(recorded.params, call.params);
+            assert_eq!(recorded.status, call.status);
+            assert_eq!(recorded.error, call.error);
+            assert_eq!(recorded.latency, call.latency);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
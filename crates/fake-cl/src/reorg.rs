@@ -0,0 +1,237 @@
+use crate::{
+    payload::{EngineVersion, NewPayload, PayloadTransaction},
+    FakeCl,
+};
+use alloy_eips::BlockNumHash;
+use alloy_primitives::{BlockHash, BlockNumber, Bytes};
+use jsonrpsee::http_client::{transport::HttpBackend, HttpClient};
+use reth::{api::EngineTypes, rpc::types::Block};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::info;
+use serde::Serialize;
+
+/// Maximum number of canonical blocks kept around to build competing branches from.
+pub const MAX_REORG_DEPTH: usize = 64;
+
+/// A competing branch that became canonical.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reorg {
+    /// Number of blocks replaced.
+    pub depth: u64,
+    /// Block number of both heads.
+    pub head_number: BlockNumber,
+    /// Head before the reorg.
+    pub old_head: BlockHash,
+    /// Head after the reorg.
+    pub new_head: BlockHash,
+    /// Hashes of the competing blocks, from lowest to highest.
+    pub new_blocks: Vec<BlockHash>,
+}
+
+impl<T: PayloadTransaction> FakeCl<T> {
+    /// Builds a competing branch replacing the last `depth` submitted blocks and makes it
+    /// canonical, which yields an `ExExNotification::ChainReorged`.
+    ///
+    /// The competing blocks are tracked as canonical until [`Self::switch_back`], or until the
+    /// next source block is submitted on top of the replaced branch.
+    ///
+    /// Competing blocks only differ on `extraData`, so they execute the same transactions. Deeper
+    /// branches also change parent hashes, which may make transactions relying on `BLOCKHASH`
+    /// behave differently. After Prague, parent hashes are also stored in state by EIP-2935, so
+    /// competing blocks above the first one would have a different state root: only depth 1 is
+    /// supported then.
+    pub async fn reorg<E: EngineTypes>(
+        &mut self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        depth: u64,
+        finalized_hash: BlockHash,
+    ) -> Result<Reorg, eyre::Error> {
+        if !self.reorged_out.is_empty() {
+            eyre::bail!("a reorg is already canonical")
+        }
+        let depth = depth as usize;
+        if depth == 0 || depth > self.canonical.len() {
+            eyre::bail!("reorg depth must be between 1 and {}", self.canonical.len())
+        }
+
+        // Checked against the highest replaced block, before submitting anything
+        let old_head = self.canonical.back().expect("not empty");
+        if self.fork_schedule.is_holocene_active_at(old_head.header.timestamp) {
+            eyre::bail!("reorgs are not supported after holocene: extraData is not free-form")
+        }
+        if depth > 1 &&
+            self.fork_schedule.version_at(old_head.header.timestamp) >= EngineVersion::V4
+        {
+            eyre::bail!(
+                "reorgs deeper than 1 block are not supported after prague: EIP-2935 stores the parent hash of each block in state"
+            )
+        }
+
+        let old_head = old_head.header.hash;
+        let mut parent_hash = self.canonical[self.canonical.len() - depth].header.parent_hash;
+        let mut siblings = Vec::with_capacity(depth);
+
+        for block in self.canonical.iter().skip(self.canonical.len() - depth) {
+            let sibling = sibling(block, parent_hash);
+            parent_hash = sibling.header.hash;
+
+            let execution_requests = self.execution_requests(block).await?;
+            let payload =
+                NewPayload::from_block(sibling.clone(), &self.fork_schedule, execution_requests)?;
+            let status = self.submit_new_payload::<E>(auth_client, payload.clone()).await?;
+            // Peers follow the reorg too, their divergences are only logged
+            self.compare_new_payload::<E>(&payload, &status).await;
+            if !status.status.is_valid() {
+                eyre::bail!(
+                    "competing block {} ({parent_hash}) was not accepted: {status:?}",
+                    block.header.number
+                )
+            }
+            siblings.push(sibling);
+        }
+
+        let head = self.canonical.back().expect("not empty");
+        let reorg = Reorg {
+            depth: depth as u64,
+            head_number: head.header.number,
+            old_head,
+            new_head: parent_hash,
+            new_blocks: siblings.iter().map(|block| block.header.hash).collect(),
+        };
+        // Safe and finalized blocks must stay below the fork point
+        let fork_point = head.header.number - reorg.depth;
+        self.set_head::<E>(auth_client, head, reorg.new_head, fork_point, finalized_hash).await?;
+
+        self.reorged_out = self.canonical.split_off(self.canonical.len() - depth);
+        for block in siblings {
+            self.track_submitted(BlockNumHash::new(block.header.number, block.header.hash));
+            self.canonical.push_back(block);
+        }
+
+        info!(target: "exex-consensus", ?reorg, "Reorged chain");
+        Ok(reorg)
+    }
+
+    /// Makes the branch replaced by `reorg` canonical again.
+    pub async fn switch_back<E: EngineTypes>(
+        &mut self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        reorg: &Reorg,
+        finalized_hash: BlockHash,
+    ) -> Result<(), eyre::Error> {
+        if self.reorged_out.back().map(|block| block.header.hash) != Some(reorg.old_head) {
+            eyre::bail!("branch {} is not reorged out", reorg.old_head)
+        }
+        let head = self.canonical.back().expect("reorged out blocks are replaced");
+        self.set_head::<E>(auth_client, head, reorg.old_head, head.header.number, finalized_hash)
+            .await?;
+        self.restore_reorged_out();
+
+        info!(target: "exex-consensus", old_head = %reorg.new_head, new_head = %reorg.old_head, "Switched back chain");
+        Ok(())
+    }
+
+    /// Tracks the branch replaced by the last reorg as canonical again, if any.
+    pub(crate) fn restore_reorged_out(&mut self) {
+        let reorged_out = std::mem::take(&mut self.reorged_out);
+        self.canonical.truncate(self.canonical.len() - reorged_out.len());
+        for block in reorged_out {
+            self.track_submitted(BlockNumHash::new(block.header.number, block.header.hash));
+            self.canonical.push_back(block);
+        }
+    }
+
+    /// Issues a `forkchoiceUpdated` request making `head_block_hash` canonical, with safe and
+    /// finalized blocks not above `max_tag`.
+    async fn set_head<E: EngineTypes>(
+        &self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        head: &Block<T>,
+        head_block_hash: BlockHash,
+        max_tag: BlockNumber,
+        finalized_hash: BlockHash,
+    ) -> Result<(), eyre::Error> {
+        let state = self
+            .forkchoice_state(
+                BlockNumHash::new(head.header.number, head_block_hash),
+                max_tag,
+                finalized_hash,
+            )
+            .await?;
+        let version = self.fork_schedule.version_at(head.header.timestamp);
+
+        let updated = self
+            .submit_forkchoice_updated::<E>(auth_client, version, state, head.header.number)
+            .await?;
+        self.compare_forkchoice_updated::<E>(
+            version,
+            state,
+            head.header.number,
+            &updated.payload_status,
+        )
+        .await;
+        if !updated.payload_status.status.is_valid() {
+            eyre::bail!("forkchoice update to {head_block_hash} was not accepted: {updated:?}")
+        }
+        Ok(())
+    }
+}
+
+/// Returns a copy of `block` on top of `parent_hash` with a different `extraData`, and therefore a
+/// different block hash.
+fn sibling<T: Clone>(block: &Block<T>, parent_hash: BlockHash) -> Block<T> {
+    let mut sibling = block.clone();
+    sibling.header.inner.parent_hash = parent_hash;
+    sibling.header.inner.extra_data = Bytes::from_static(b"fake-cl reorg");
+    sibling.header.hash = sibling.header.inner.hash_slow();
+    sibling
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payload::ForkSchedule, tests::Empty};
+    use jsonrpsee::http_client::HttpClientBuilder;
+    use reth::{chainspec::MAINNET, rpc::types::Transaction};
+    use reth_node_ethereum::EthEngineTypes;
+    use reth_rpc_layer::{AuthClientLayer, JwtSecret};
+
+    #[tokio::test]
+    async fn rejects_deep_reorgs_after_prague() {
+        let mut fake_cl = FakeCl::new(Empty, ForkSchedule::new(MAINNET.as_ref()));
+        for number in 1..=2 {
+            let mut block = Block::<Transaction>::default();
+            block.header.inner.number = number;
+            block.header.inner.timestamp = 1_800_000_000 + number;
+            block.header.hash = block.header.inner.hash_slow();
+            fake_cl.canonical.push_back(block);
+        }
+        // Nothing is listening, so any submitted request would fail differently
+        let middleware =
+            tower::ServiceBuilder::default().layer(AuthClientLayer::new(JwtSecret::random()));
+        let auth_client = HttpClientBuilder::default()
+            .set_http_middleware(middleware)
+            .build("http://127.0.0.1:1")
+            .unwrap();
+
+        let err =
+            fake_cl.reorg::<EthEngineTypes>(&auth_client, 2, BlockHash::ZERO).await.unwrap_err();
+        assert!(err.to_string().contains("EIP-2935"), "{err}");
+        assert_eq!(fake_cl.canonical.len(), 2);
+        assert!(fake_cl.reorged_out.is_empty());
+    }
+
+    #[test]
+    fn siblings_compete_with_the_original_block() {
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.number = 10;
+        block.header.hash = block.header.inner.hash_slow();
+        let parent_hash = BlockHash::repeat_byte(0x01);
+
+        let sibling = sibling(&block, parent_hash);
+        assert_eq!(sibling.header.number, 10);
+        assert_eq!(sibling.header.parent_hash, parent_hash);
+        assert_eq!(sibling.header.hash, sibling.header.inner.hash_slow());
+        assert_ne!(sibling.header.hash, block.header.hash);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    bench::{Bench, BenchSummary},
+    payload::PayloadTransaction,
+    status::PayloadOutcome,
+    FakeCl,
+};
+use alloy_primitives::{BlockHash, BlockNumber};
+use jsonrpsee::http_client::{transport::HttpBackend, HttpClient};
+use reth::{api::EngineTypes, rpc::types::Transaction};
+use reth_rpc_layer::AuthClientService;
+use reth_tracing::tracing::{info, warn};
+use std::time::Duration;
+
+/// Delay between polls of the block source tip once the chain has caught up to it.
+pub const SOURCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Moves a node forward one block at a time with a [`FakeCl`], tracking the local and source
+/// tips and benchmarking every block if enabled.
+///
+/// Shared by the ExEx and the out-of-process CLI, which only differ in what they check after each
+/// block.
+pub struct Runner<T = Transaction> {
+    fake_cl: FakeCl<T>,
+    /// Latencies of every `VALID` block, if benchmarking.
+    bench: Option<Bench>,
+    /// Last block submitted to the node.
+    local_tip: BlockNumber,
+    /// Last known block of the source.
+    source_tip: BlockNumber,
+}
+
+impl<T: PayloadTransaction> Runner<T> {
+    /// Starts on top of the node head at `local_tip`.
+    pub async fn new(
+        fake_cl: FakeCl<T>,
+        local_tip: BlockNumber,
+        bench: bool,
+    ) -> Result<Self, eyre::Error> {
+        let source_tip = fake_cl.source_tip().await?;
+        Ok(Self { fake_cl, bench: bench.then(Bench::default), local_tip, source_tip })
+    }
+
+    /// Returns the underlying [`FakeCl`].
+    pub const fn fake_cl(&self) -> &FakeCl<T> {
+        &self.fake_cl
+    }
+
+    /// Returns the underlying [`FakeCl`] mutably, eg. to inject reorgs.
+    pub fn fake_cl_mut(&mut self) -> &mut FakeCl<T> {
+        &mut self.fake_cl
+    }
+
+    /// Returns the last block submitted to the node.
+    pub const fn local_tip(&self) -> BlockNumber {
+        self.local_tip
+    }
+
+    /// Returns the last known block of the source.
+    pub const fn source_tip(&self) -> BlockNumber {
+        self.source_tip
+    }
+
+    /// Whether every known source block has been submitted.
+    ///
+    /// Never while benchmarking: the source is offline, so blocks past its tip are misses failing
+    /// the run rather than blocks to wait for.
+    pub const fn is_caught_up(&self) -> bool {
+        self.bench.is_none() && self.local_tip >= self.source_tip
+    }
+
+    /// Waits for [`SOURCE_POLL_INTERVAL`] and refreshes the source tip, keeping the previous one
+    /// on failure.
+    pub async fn poll_source(&mut self) {
+        tokio::time::sleep(SOURCE_POLL_INTERVAL).await;
+
+        match self.fake_cl.source_tip().await {
+            Ok(tip) => self.source_tip = tip,
+            Err(err) => warn!(%err, source_tip = self.source_tip, "Failed to refresh source tip"),
+        }
+    }
+
+    /// Submits the next block and returns its outcome once `VALID`.
+    ///
+    /// `report` is called with the outcome before it is checked, so that invalid outcomes can be
+    /// reported too.
+    pub async fn advance<E: EngineTypes>(
+        &mut self,
+        auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+        finalized_hash: BlockHash,
+        report: impl FnOnce(&PayloadOutcome),
+    ) -> Result<PayloadOutcome, eyre::Error> {
+        info!(source_tip = self.source_tip, local_tip = self.local_tip, "Advancing chain");
+        let outcome = self
+            .fake_cl
+            .advance_chain::<E>(auth_client, self.local_tip + 1, finalized_hash)
+            .await?;
+
+        report(&outcome);
+        outcome.ensure_valid()?;
+        self.local_tip += 1;
+
+        if let Some(bench) = &mut self.bench {
+            bench.record(&outcome);
+            info!(
+                block_number = outcome.block_number,
+                new_payload = ?outcome.new_payload_latency,
+                forkchoice_updated = ?outcome.forkchoice_updated_latency,
+                gas_used = outcome.gas_used,
+                "Benchmarked block"
+            );
+        }
+
+        Ok(outcome)
+    }
+
+    /// Returns the [`BenchSummary`] of the run once, if benchmarking.
+    pub fn take_bench_summary(&mut self) -> Option<BenchSummary> {
+        let summary = self.bench.take()?.summary();
+        info!("Bench results:\n{summary}");
+        Some(summary)
+    }
+}
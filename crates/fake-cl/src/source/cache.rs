@@ -0,0 +1,242 @@
+use super::BlockSource;
+use crate::payload::PayloadTransaction;
+use alloy_primitives::{Address, BlockNumber, Log};
+use futures::future::BoxFuture;
+use reth::rpc::types::{Block, BlockNumberOrTag, Transaction};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A [`BlockSource`] that records every fetched block to disk and replays it on later runs.
+///
+/// Blocks are stored as `<dir>/<chain_id>/<block_number>.json`, so caches of different chains
+/// can share a directory, and their logs as `<block_number>.logs.json`. Without an inner source, it
+/// only replays what has been recorded before.
+pub struct CachedBlockSource<T = Transaction> {
+    /// Source queried on cache misses.
+    inner: Option<Box<dyn BlockSource<T>>>,
+    /// Cache directory of the chain.
+    dir: PathBuf,
+    /// Highest block number in the cache. Zero if empty.
+    highest: AtomicU64,
+    /// Last block number served by [`BlockSource::block`].
+    last_served: AtomicU64,
+}
+
+impl<T> CachedBlockSource<T> {
+    /// Opens (or creates) the cache of `chain_id` at `dir`, falling back to `inner` on cache
+    /// misses.
+    pub fn new(
+        dir: impl AsRef<Path>,
+        chain_id: u64,
+        inner: Option<Box<dyn BlockSource<T>>>,
+    ) -> Result<Self, eyre::Error> {
+        let dir = dir.as_ref().join(chain_id.to_string());
+        std::fs::create_dir_all(&dir)?;
+
+        let mut highest = 0;
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if let Some(number) = cached_block_number(&path) {
+                highest = highest.max(number);
+            }
+        }
+
+        Ok(Self { inner, dir, highest: AtomicU64::new(highest), last_served: AtomicU64::new(0) })
+    }
+
+    /// Returns the path of a cached block.
+    fn path(&self, number: BlockNumber) -> PathBuf {
+        self.dir.join(format!("{number}.json"))
+    }
+
+    /// Returns the path of the cached logs of a block.
+    fn logs_path(&self, number: BlockNumber) -> PathBuf {
+        self.dir.join(format!("{number}.logs.json"))
+    }
+}
+
+/// Returns the block number of a cache file, if `path` is one.
+fn cached_block_number(path: &Path) -> Option<BlockNumber> {
+    if path.extension()? != "json" {
+        return None
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Writes `contents` to a temporary file renamed to `path`, so that a crash never leaves a
+/// truncated block behind.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, path)
+}
+
+impl<T: PayloadTransaction> BlockSource<T> for CachedBlockSource<T> {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        Box::pin(async move {
+            let highest = self.highest.load(Ordering::Relaxed);
+
+            // Only hit the inner source once every cached block ahead of us has been replayed.
+            match &self.inner {
+                Some(inner) if highest <= self.last_served.load(Ordering::Relaxed) => {
+                    inner.latest().await
+                }
+                _ if highest > 0 => Ok(highest),
+                _ => Err(eyre::eyre!("block cache at {} is empty", self.dir.display())),
+            }
+        })
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<T>>> {
+        Box::pin(async move {
+            let path = self.path(number);
+
+            let block: Block<T> = if path.exists() {
+                serde_json::from_slice(&std::fs::read(&path)?)?
+            } else {
+                let inner = self.inner.as_ref().ok_or_else(|| {
+                    eyre::eyre!("block {number} not found on cache {}", self.dir.display())
+                })?;
+                let block = inner.block(number).await?;
+                write_atomic(&path, &serde_json::to_vec(&block)?)?;
+                self.highest.fetch_max(number, Ordering::Relaxed);
+                block
+            };
+
+            self.last_served.store(number, Ordering::Relaxed);
+            Ok(block)
+        })
+    }
+
+    fn tagged(&self, tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        Box::pin(async move {
+            match &self.inner {
+                Some(inner) => inner.tagged(tag).await,
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn logs(
+        &self,
+        number: BlockNumber,
+        addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        // The addresses only depend on the chain, so logs are cached by block number
+        Box::pin(async move {
+            let path = self.logs_path(number);
+            if path.exists() {
+                return Ok(serde_json::from_slice(&std::fs::read(&path)?)?)
+            }
+
+            let inner = self.inner.as_ref().ok_or_else(|| {
+                eyre::eyre!("logs of block {number} not found on cache {}", self.dir.display())
+            })?;
+            let logs = inner.logs(number, addresses).await?;
+            write_atomic(&path, &serde_json::to_vec(&logs)?)?;
+            Ok(logs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+
+    /// Returns an empty directory unique to `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fake-cl-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn replays_fixtures_offline() {
+        let cache = CachedBlockSource::<Transaction>::new(FIXTURES, 1, None).unwrap();
+
+        assert_eq!(cache.latest().await.unwrap(), 21000002);
+        let block = cache.block(21000001).await.unwrap();
+        assert_eq!(block.header.number, 21000001);
+        assert!(cache.block(1).await.unwrap_err().to_string().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn records_misses() {
+        let dir = temp_dir("records-misses");
+        let fixtures = CachedBlockSource::<Transaction>::new(FIXTURES, 1, None).unwrap();
+        let cache = CachedBlockSource::new(&dir, 1, Some(Box::new(fixtures))).unwrap();
+
+        let block = cache.block(21000000).await.unwrap();
+        assert!(dir.join("1/21000000.json").exists());
+        assert!(!dir.join("1/21000000.json.tmp").exists());
+
+        let replay = CachedBlockSource::<Transaction>::new(&dir, 1, None).unwrap();
+        assert_eq!(replay.latest().await.unwrap(), 21000000);
+        assert_eq!(replay.block(21000000).await.unwrap().header.hash, block.header.hash);
+    }
+
+    /// Serves a single log emitted by the first requested address in every block.
+    struct OneLog;
+
+    impl BlockSource for OneLog {
+        fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+            Box::pin(async { Ok(0) })
+        }
+
+        fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+            Box::pin(async move { eyre::bail!("block {number} not found") })
+        }
+
+        fn logs(
+            &self,
+            number: BlockNumber,
+            addresses: Vec<Address>,
+        ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+            let log = Log::new_unchecked(addresses[0], Vec::new(), number.to_be_bytes().into());
+            Box::pin(async move { Ok(vec![log]) })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_logs() {
+        let dir = temp_dir("records-logs");
+        let cache = CachedBlockSource::new(&dir, 1, Some(Box::new(OneLog))).unwrap();
+        let addresses = vec![Address::repeat_byte(0x01)];
+
+        let logs = cache.logs(7, addresses.clone()).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert!(dir.join("1/7.logs.json").exists());
+
+        let replay = CachedBlockSource::<Transaction>::new(&dir, 1, None).unwrap();
+        assert_eq!(replay.logs(7, addresses.clone()).await.unwrap(), logs);
+        assert!(replay.logs(8, addresses).await.unwrap_err().to_string().contains("not found"));
+        // Cached logs are not mistaken for blocks
+        assert!(replay.latest().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn isolates_chains() {
+        let dir = temp_dir("isolates-chains");
+        let fixtures = CachedBlockSource::<Transaction>::new(FIXTURES, 1, None).unwrap();
+        let cache = CachedBlockSource::new(&dir, 1, Some(Box::new(fixtures))).unwrap();
+        cache.block(21000000).await.unwrap();
+
+        let other = CachedBlockSource::<Transaction>::new(&dir, 10, None).unwrap();
+        assert!(other.latest().await.is_err());
+        assert!(other.block(21000000).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ignores_interrupted_writes() {
+        let dir = temp_dir("interrupted-writes");
+        std::fs::create_dir_all(dir.join("1")).unwrap();
+        std::fs::write(dir.join("1/21000000.json.tmp"), b"{\"hash\":").unwrap();
+
+        let cache = CachedBlockSource::<Transaction>::new(&dir, 1, None).unwrap();
+        assert!(cache.latest().await.is_err());
+    }
+}
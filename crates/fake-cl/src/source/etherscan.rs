@@ -0,0 +1,328 @@
+use super::{BlockSource, RateLimited};
+use alloy_chains::Chain;
+use alloy_primitives::{Address, BlockNumber, Bytes, Log, B256};
+use futures::future::BoxFuture;
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use reth::rpc::types::{Block, BlockNumberOrTag, Header};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::time::Duration;
+
+/// Time after which an etherscan request is considered failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Creates an [`EtherscanBlockSource`] from a [`Chain`] and `etherscan_url` if it exists.
+///
+/// Requires an etherscan api key to be set as an environment variable.
+pub fn etherscan_provider(
+    chain: Chain,
+    etherscan_url: Option<String>,
+) -> Result<EtherscanBlockSource, eyre::Error> {
+    let etherscan_url = etherscan_url.map(Ok).unwrap_or_else(|| {
+        chain
+            .etherscan_urls()
+            .map(|urls| urls.0.to_string())
+            .ok_or_else(|| eyre::eyre!("failed to get etherscan url for chain: {chain}"))
+    })?;
+    let etherscan_api_key = chain.etherscan_api_key().ok_or_else(|| {
+        eyre::eyre!("etherscan api key not found for rpc consensus client for chain: {chain}")
+    })?;
+
+    EtherscanBlockSource::new(etherscan_url, etherscan_api_key)
+}
+
+/// A [`BlockSource`] backed by the etherscan `proxy` module, and the `logs` module for logs.
+///
+/// Rate limit responses, either HTTP `429` or etherscan `NOTOK` results, are surfaced as
+/// [`RateLimited`] errors.
+#[derive(Debug, Clone)]
+pub struct EtherscanBlockSource {
+    client: reqwest::Client,
+    /// Etherscan API endpoint, eg. `https://api.etherscan.io/api`.
+    base_url: String,
+    api_key: String,
+}
+
+/// Body of an etherscan response.
+///
+/// Variants are tried in order, and a missing `result` deserializes as `None`, so errors come
+/// first. The `logs` module answers `No records found` with an empty `result` array, which is
+/// a successful response.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ProxyResponse<R> {
+    /// JSON-RPC error forwarded from the node.
+    RpcError { error: serde_json::Value },
+    /// Etherscan error, eg. rate limits or invalid api keys.
+    Etherscan { message: String, result: String },
+    /// Successful JSON-RPC response.
+    Result { result: Option<R> },
+}
+
+/// Log returned by the etherscan `logs` module, whose other fields don't follow the JSON-RPC
+/// encoding.
+#[derive(Debug, Deserialize)]
+struct EtherscanLog {
+    address: Address,
+    topics: Vec<B256>,
+    data: Bytes,
+}
+
+impl EtherscanBlockSource {
+    /// Creates a [`Self`] querying `base_url` with `api_key`.
+    pub fn new(
+        base_url: impl Into<String>,
+        api_key: impl Into<String>,
+    ) -> Result<Self, eyre::Error> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            base_url: base_url.into(),
+            api_key: api_key.into(),
+        })
+    }
+
+    /// Fetches a block with its full transactions through `eth_getBlockByNumber`.
+    pub async fn load_block(&self, tag: BlockNumberOrTag) -> Result<Block, eyre::Error> {
+        self.get_block_by_number::<Box<Block>>(tag, true)
+            .await?
+            .map(|block| *block)
+            .ok_or_else(|| eyre::eyre!("block {tag} not found on etherscan"))
+    }
+
+    /// Issues `eth_getBlockByNumber`, decoding the result as `R`.
+    ///
+    /// Without `full` transactions, only their hashes are returned, so `R` can be a [`Header`].
+    async fn get_block_by_number<R: DeserializeOwned>(
+        &self,
+        tag: BlockNumberOrTag,
+        full: bool,
+    ) -> Result<Option<R>, eyre::Error> {
+        // `BlockNumberOrTag` displays numbers as `number 0x..`
+        let tag_param = match tag {
+            BlockNumberOrTag::Number(number) => format!("{number:#x}"),
+            tag => tag.to_string(),
+        };
+        self.get(
+            &[
+                ("module", "proxy"),
+                ("action", "eth_getBlockByNumber"),
+                ("tag", &tag_param),
+                ("boolean", if full { "true" } else { "false" }),
+            ],
+            &format!("block {tag}"),
+        )
+        .await
+    }
+
+    /// Issues a request with `query`, decoding the result as `R`. Errors mention `what` was
+    /// requested.
+    async fn get<R: DeserializeOwned>(
+        &self,
+        query: &[(&str, &str)],
+        what: &str,
+    ) -> Result<Option<R>, eyre::Error> {
+        let response = self
+            .client
+            .get(&self.base_url)
+            .query(query)
+            .query(&[("apikey", &self.api_key)])
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map(Duration::from_secs);
+            return Err(RateLimited { retry_after }.into())
+        }
+
+        match response.error_for_status()?.json().await? {
+            ProxyResponse::Result { result } => Ok(result),
+            ProxyResponse::RpcError { error } => {
+                Err(eyre::eyre!("etherscan failed to get {what}: {error}"))
+            }
+            ProxyResponse::Etherscan { result, .. }
+                if result.to_lowercase().contains("rate limit") =>
+            {
+                Err(RateLimited { retry_after: None }.into())
+            }
+            ProxyResponse::Etherscan { message, result } => {
+                Err(eyre::eyre!("etherscan failed to get {what}: {message}: {result}"))
+            }
+        }
+    }
+}
+
+impl BlockSource for EtherscanBlockSource {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        Box::pin(async move {
+            self.get_block_by_number::<Header>(BlockNumberOrTag::Latest, false)
+                .await?
+                .map(|header| header.number)
+                .ok_or_else(|| eyre::eyre!("latest block not found on etherscan"))
+        })
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+        Box::pin(self.load_block(number.into()))
+    }
+
+    fn tagged(&self, tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        Box::pin(async move {
+            Ok(self.get_block_by_number::<Header>(tag, false).await?.map(|header| header.number))
+        })
+    }
+
+    fn logs(
+        &self,
+        number: BlockNumber,
+        addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        // The `logs` module filters a single address, so logs are grouped by address
+        Box::pin(async move {
+            let block = number.to_string();
+            let mut logs = Vec::new();
+            for address in addresses {
+                let found: Option<Vec<EtherscanLog>> = self
+                    .get(
+                        &[
+                            ("module", "logs"),
+                            ("action", "getLogs"),
+                            ("fromBlock", &block),
+                            ("toBlock", &block),
+                            ("address", &address.to_string()),
+                        ],
+                        &format!("logs of block {number}"),
+                    )
+                    .await?;
+                logs.extend(
+                    found
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|log| Log::new_unchecked(log.address, log.topics, log.data)),
+                );
+            }
+            Ok(logs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_etherscan::{Failures, MockEtherscan};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/1");
+
+    /// Serves the mainnet fixtures with `failures`, and returns a source querying them.
+    async fn mock(failures: Failures) -> EtherscanBlockSource {
+        let mock = MockEtherscan::from_dir(FIXTURES).unwrap().with_failures(failures);
+        let (addr, _) = mock.spawn(([127, 0, 0, 1], 0).into()).await.unwrap();
+        EtherscanBlockSource::new(format!("http://{addr}/api"), "mock").unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_blocks() {
+        let source = mock(Failures::default()).await;
+
+        assert_eq!(source.latest().await.unwrap(), 21000002);
+        let block = source.block(21000001).await.unwrap();
+        assert_eq!(block.header.number, 21000001);
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(source.block(21000002).await.unwrap().header.parent_hash, block.header.hash);
+    }
+
+    #[tokio::test]
+    async fn resolves_tags_from_headers() {
+        let source = mock(Failures::default()).await;
+
+        let tagged = source.tagged(BlockNumberOrTag::Finalized).await.unwrap();
+        assert_eq!(tagged, Some(21000002));
+    }
+
+    #[tokio::test]
+    async fn fetches_logs_of_each_address() {
+        let deposit_contract = Address::repeat_byte(0x01);
+        let log = serde_json::json!({
+            "address": deposit_contract,
+            "topics": [B256::repeat_byte(0x02)],
+            "data": "0x03",
+            "blockNumber": "0x1406f40",
+            "logIndex": "0x",
+        });
+        let mock = MockEtherscan::from_dir(FIXTURES)
+            .unwrap()
+            .with_logs([(21000000, vec![log])].into_iter().collect());
+        let (addr, _) = mock.spawn(([127, 0, 0, 1], 0).into()).await.unwrap();
+        let source = EtherscanBlockSource::new(format!("http://{addr}/api"), "mock").unwrap();
+
+        let addresses = vec![deposit_contract, Address::repeat_byte(0x04)];
+        let logs = source.logs(21000000, addresses.clone()).await.unwrap();
+        assert_eq!(
+            logs,
+            vec![Log::new_unchecked(
+                deposit_contract,
+                vec![B256::repeat_byte(0x02)],
+                Bytes::from_static(&[0x03])
+            )]
+        );
+        assert!(source.logs(21000001, addresses).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_block_is_not_found() {
+        let source = mock(Failures::default()).await;
+
+        let err = source.block(1).await.unwrap_err();
+        assert!(err.to_string().contains("not found"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_is_rate_limited() {
+        let source = mock(Failures {
+            rate_limit_every: Some(1),
+            retry_after: Some(2),
+            ..Default::default()
+        })
+        .await;
+
+        let err = source.block(21000000).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RateLimited>(),
+            Some(&RateLimited { retry_after: Some(Duration::from_secs(2)) })
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit_message_is_rate_limited() {
+        let source = mock(Failures { rate_limit_every: Some(1), ..Default::default() }).await;
+
+        let err = source.block(21000000).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<RateLimited>(), Some(&RateLimited { retry_after: None }));
+    }
+
+    #[tokio::test]
+    async fn notok_is_an_error() {
+        let source = mock(Failures { api_error_every: Some(1), ..Default::default() }).await;
+
+        let err = source.block(21000000).await.unwrap_err();
+        assert!(err.downcast_ref::<RateLimited>().is_none());
+        assert!(err.to_string().contains("Invalid API Key"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn rpc_error_is_surfaced() {
+        let source = mock(Failures { rpc_error_every: Some(1), ..Default::default() }).await;
+
+        let err = source.block(21000000).await.unwrap_err();
+        assert!(err.to_string().contains("header not found"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_an_error() {
+        let source = mock(Failures { malformed_every: Some(1), ..Default::default() }).await;
+
+        assert!(source.block(21000000).await.is_err());
+    }
+}
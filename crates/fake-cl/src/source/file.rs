@@ -0,0 +1,111 @@
+use super::BlockSource;
+use alloy_primitives::{BlockNumber, U256};
+use alloy_rlp::Decodable;
+use futures::future::BoxFuture;
+use reth::rpc::types::{Block, BlockTransactionsKind};
+use reth_rpc::eth::EthTxBuilder;
+use reth_rpc_types_compat::block::from_block;
+use std::{collections::BTreeMap, path::Path};
+
+/// A [`BlockSource`] that replays blocks from a local file, with no network access.
+///
+/// Reads RLP chain files (the format read by `reth import`). Only post-merge blocks are accepted,
+/// since pre-merge blocks can never be valid engine API payloads.
+#[derive(Debug, Default)]
+pub struct FileBlockSource {
+    /// Decoded blocks.
+    blocks: BTreeMap<BlockNumber, reth_primitives::Block>,
+}
+
+impl FileBlockSource {
+    /// Loads every block of an RLP-encoded chain file.
+    pub fn from_rlp(path: impl AsRef<Path>) -> Result<Self, eyre::Error> {
+        let data = std::fs::read(path.as_ref())?;
+        let mut buf = data.as_slice();
+        let mut source = Self::default();
+
+        while !buf.is_empty() {
+            let block = reth_primitives::Block::decode(&mut buf)?;
+            if !block.header.difficulty.is_zero() {
+                eyre::bail!(
+                    "block {} of {} is pre-merge, only post-merge blocks can be replayed",
+                    block.header.number,
+                    path.as_ref().display()
+                )
+            }
+            source.blocks.insert(block.header.number, block);
+        }
+
+        Ok(source)
+    }
+}
+
+impl BlockSource for FileBlockSource {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        Box::pin(async move {
+            self.blocks
+                .last_key_value()
+                .map(|(number, _)| *number)
+                .ok_or_else(|| eyre::eyre!("file source has no blocks"))
+        })
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+        Box::pin(async move {
+            let block = self
+                .blocks
+                .get(&number)
+                .cloned()
+                .ok_or_else(|| eyre::eyre!("block {number} not found on file source"))?;
+            let block_hash = block.header.hash_slow();
+            let block = block
+                .with_recovered_senders()
+                .ok_or_else(|| eyre::eyre!("failed to recover senders of block {number}"))?;
+
+            Ok(from_block(
+                block,
+                U256::ZERO,
+                BlockTransactionsKind::Full,
+                Some(block_hash),
+                &EthTxBuilder,
+            )?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_rlp::Encodable;
+
+    /// Writes `blocks` as an RLP chain file and loads it.
+    fn load(name: &str, blocks: &[reth_primitives::Block]) -> eyre::Result<FileBlockSource> {
+        let path = std::env::temp_dir().join(format!("fake-cl-{}-{name}.rlp", std::process::id()));
+        let mut data = Vec::new();
+        blocks.iter().for_each(|block| block.encode(&mut data));
+        std::fs::write(&path, data)?;
+        FileBlockSource::from_rlp(path)
+    }
+
+    fn block(number: BlockNumber, difficulty: u64) -> reth_primitives::Block {
+        let mut block = reth_primitives::Block::default();
+        block.header.number = number;
+        block.header.difficulty = U256::from(difficulty);
+        block
+    }
+
+    #[tokio::test]
+    async fn replays_post_merge_blocks() {
+        let source = load("post-merge", &[block(1, 0), block(2, 0)]).unwrap();
+
+        assert_eq!(source.latest().await.unwrap(), 2);
+        assert_eq!(source.block(1).await.unwrap().header.number, 1);
+        assert!(source.block(3).await.is_err());
+    }
+
+    #[test]
+    fn rejects_pre_merge_blocks() {
+        let err = load("pre-merge", &[block(1, 0), block(2, 1)]).unwrap_err();
+        assert!(err.to_string().contains("block 2"));
+    }
+}
@@ -0,0 +1,107 @@
+use alloy_primitives::{Address, BlockNumber, Log};
+use futures::future::BoxFuture;
+use reth::rpc::types::{Block, BlockNumberOrTag, Transaction};
+
+mod etherscan;
+pub use etherscan::{etherscan_provider, EtherscanBlockSource};
+
+mod rpc;
+pub use rpc::RpcBlockSource;
+
+mod file;
+pub use file::FileBlockSource;
+
+mod cache;
+pub use cache::CachedBlockSource;
+
+mod rate_limit;
+pub use rate_limit::{RateLimit, RateLimited, RateLimitedBlockSource};
+
+/// A source of blocks which [`FakeCl`](crate::FakeCl) turns into engine API calls.
+///
+/// `T` is the RPC transaction type of the chain.
+pub trait BlockSource<T = Transaction>: Send + Sync {
+    /// Returns the latest block number known by the source.
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>>;
+
+    /// Returns the block at `number` with its full transactions.
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<T>>>;
+
+    /// Returns the number of the block the source tags as `safe` or `finalized`, if it tracks
+    /// them.
+    fn tagged(&self, _tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Returns the logs emitted by `addresses` in the block at `number`, in block order. The
+    /// execution requests of Prague blocks are derived from them.
+    fn logs(
+        &self,
+        number: BlockNumber,
+        _addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        Box::pin(
+            async move { eyre::bail!("block source does not provide the logs of block {number}") },
+        )
+    }
+}
+
+impl<T, S: BlockSource<T> + ?Sized> BlockSource<T> for Box<S> {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        (**self).latest()
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<T>>> {
+        (**self).block(number)
+    }
+
+    fn tagged(&self, tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        (**self).tagged(tag)
+    }
+
+    fn logs(
+        &self,
+        number: BlockNumber,
+        addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        (**self).logs(number, addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves empty blocks up to `latest`.
+    struct Empty {
+        latest: BlockNumber,
+    }
+
+    impl BlockSource for Empty {
+        fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+            Box::pin(async move { Ok(self.latest) })
+        }
+
+        fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block>> {
+            Box::pin(async move {
+                if number > self.latest {
+                    eyre::bail!("block {number} not found")
+                }
+                let mut block = Block::<Transaction>::default();
+                block.header.inner.number = number;
+                Ok(block)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn boxed_sources_forward_to_their_inner_source() {
+        let source: Box<dyn BlockSource> = Box::new(Empty { latest: 10 });
+
+        assert_eq!(source.latest().await.unwrap(), 10);
+        assert_eq!(source.block(7).await.unwrap().header.number, 7);
+        assert!(source.block(11).await.is_err());
+        assert_eq!(source.tagged(BlockNumberOrTag::Finalized).await.unwrap(), None);
+        assert!(source.logs(7, Vec::new()).await.is_err());
+    }
+}
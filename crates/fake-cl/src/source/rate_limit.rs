@@ -0,0 +1,235 @@
+use super::BlockSource;
+use alloy_primitives::{Address, BlockNumber, Log};
+use futures::future::BoxFuture;
+use reth::rpc::types::{Block, BlockNumberOrTag, Transaction};
+use reth_tracing::tracing::warn;
+use std::{fmt, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+
+/// Error returned by a [`BlockSource`] when the remote signals a rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    /// How long the remote asked to wait before the next request, if it did.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limited by block source")?;
+        if let Some(retry_after) = self.retry_after {
+            write!(f, ", retry after {retry_after:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Pacing and retry configuration of a [`RateLimitedBlockSource`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Maximum number of requests per second.
+    pub requests_per_second: u32,
+    /// Number of retries of a failed request before giving up.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub initial_backoff: Duration,
+    /// Upper bound of the retry delay.
+    pub max_backoff: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 4,
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A [`BlockSource`] that paces requests to another one and retries transient failures with
+/// exponential backoff.
+///
+/// A failed request holds back every request for its backoff, or the `retry_after` of a
+/// [`RateLimited`] error if any.
+pub struct RateLimitedBlockSource<T = Transaction> {
+    inner: Box<dyn BlockSource<T>>,
+    limit: RateLimit,
+    /// Earliest time the next request can be sent.
+    next_request: Mutex<Instant>,
+}
+
+impl<T> RateLimitedBlockSource<T> {
+    /// Wraps `inner` with a [`RateLimit`].
+    pub fn new(inner: impl BlockSource<T> + 'static, limit: RateLimit) -> Self {
+        Self { inner: Box::new(inner), limit, next_request: Mutex::new(Instant::now()) }
+    }
+
+    /// Waits until the next request is allowed, and at least `delay`, then reserves the following
+    /// slot.
+    async fn wait_turn(&self, delay: Duration) {
+        let not_before = Instant::now() + delay;
+        let mut next_request = self.next_request.lock().await;
+        tokio::time::sleep_until((*next_request).max(not_before)).await;
+
+        let interval = Duration::from_secs(1) / self.limit.requests_per_second.max(1);
+        *next_request = Instant::now() + interval;
+    }
+
+    /// Sends `request` once it is allowed, retrying it on transient errors.
+    async fn call<'a, R>(
+        &'a self,
+        request: impl Fn() -> BoxFuture<'a, eyre::Result<R>>,
+    ) -> eyre::Result<R> {
+        let mut backoff = self.limit.initial_backoff;
+        let mut delay = Duration::ZERO;
+        let mut retries = 0;
+        loop {
+            self.wait_turn(delay).await;
+
+            let err = match request().await {
+                Ok(response) => return Ok(response),
+                Err(err) if retries >= self.limit.max_retries || !is_transient(&err) => {
+                    return Err(err)
+                }
+                Err(err) => err,
+            };
+
+            retries += 1;
+            delay = err
+                .downcast_ref::<RateLimited>()
+                .and_then(|rate_limited| rate_limited.retry_after)
+                .unwrap_or(backoff);
+            backoff = (backoff * 2).min(self.limit.max_backoff);
+
+            warn!(target: "exex-consensus", %err, retries, ?delay, "block source request failed, backing off");
+        }
+    }
+}
+
+/// Whether retrying may succeed after `err`: rate limits, timeouts, connection failures and
+/// server errors.
+///
+/// Invalid api keys, malformed responses and JSON-RPC errors are returned right away.
+fn is_transient(err: &eyre::Report) -> bool {
+    if err.downcast_ref::<RateLimited>().is_some() {
+        return true
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout() ||
+            err.is_connect() ||
+            err.status().is_some_and(|status| status.is_server_error())
+    }
+    matches!(
+        err.downcast_ref::<jsonrpsee::core::ClientError>(),
+        Some(
+            jsonrpsee::core::ClientError::Transport(_) |
+                jsonrpsee::core::ClientError::RestartNeeded(_) |
+                jsonrpsee::core::ClientError::RequestTimeout
+        )
+    )
+}
+
+impl<T: Send + Sync> BlockSource<T> for RateLimitedBlockSource<T> {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        Box::pin(self.call(|| self.inner.latest()))
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<T>>> {
+        Box::pin(self.call(move || self.inner.block(number)))
+    }
+
+    fn tagged(&self, tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        Box::pin(self.call(move || self.inner.tagged(tag)))
+    }
+
+    fn logs(
+        &self,
+        number: BlockNumber,
+        addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        Box::pin(self.call(move || self.inner.logs(number, addresses.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::EtherscanBlockSource;
+    use mock_etherscan::{Failures, MockEtherscan};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/1");
+
+    /// Fast retries, so tests don't wait on backoffs.
+    const LIMIT: RateLimit = RateLimit {
+        requests_per_second: 100,
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(10),
+    };
+
+    /// Serves the mainnet fixtures with `failures`, and returns the mock and a rate limited
+    /// etherscan source querying it.
+    async fn mock(failures: Failures, limit: RateLimit) -> (MockEtherscan, RateLimitedBlockSource) {
+        let mock = MockEtherscan::from_dir(FIXTURES).unwrap().with_failures(failures);
+        let (addr, _) = mock.spawn(([127, 0, 0, 1], 0).into()).await.unwrap();
+        let source = EtherscanBlockSource::new(format!("http://{addr}/api"), "mock").unwrap();
+        (mock, RateLimitedBlockSource::new(source, limit))
+    }
+
+    #[tokio::test]
+    async fn retries_through_rate_limits() {
+        let (mock, source) = mock(
+            Failures { rate_limit_every: Some(2), ..Default::default() },
+            RateLimit { max_retries: 10, ..LIMIT },
+        )
+        .await;
+
+        for number in 21000000..=21000002 {
+            assert_eq!(source.block(number).await.unwrap().header.number, number);
+        }
+        assert_eq!(source.latest().await.unwrap(), 21000002);
+        assert!(mock.requests() > 4);
+    }
+
+    #[tokio::test]
+    async fn waits_for_retry_after() {
+        let (_mock, source) = mock(
+            Failures { rate_limit_every: Some(2), retry_after: Some(1), ..Default::default() },
+            LIMIT,
+        )
+        .await;
+
+        let started_at = Instant::now();
+        source.block(21000000).await.unwrap();
+        source.block(21000001).await.unwrap();
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn returns_permanent_errors_without_retrying() {
+        for (failures, error) in [
+            (Failures { api_error_every: Some(1), ..Default::default() }, "Invalid API Key"),
+            (Failures { malformed_every: Some(1), ..Default::default() }, "decoding"),
+            (Failures { rpc_error_every: Some(1), ..Default::default() }, "etherscan failed"),
+        ] {
+            let (mock, source) = mock(failures, LIMIT).await;
+
+            let err = source.block(21000000).await.unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+            assert_eq!(mock.requests(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_persistent_rate_limits() {
+        let (mock, source) =
+            mock(Failures { rate_limit_every: Some(1), ..Default::default() }, LIMIT).await;
+
+        let err = source.latest().await.unwrap_err();
+        assert!(err.downcast_ref::<RateLimited>().is_some());
+        assert_eq!(mock.requests(), LIMIT.max_retries as u64 + 1);
+    }
+}
@@ -0,0 +1,169 @@
+use super::BlockSource;
+use crate::payload::PayloadTransaction;
+use alloy_primitives::{Address, BlockNumber, Log};
+use futures::future::BoxFuture;
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use reth::rpc::{
+    api::EthApiClient,
+    types::{Block, BlockNumberOrTag, Filter, Header, Log as RpcLog, Receipt},
+};
+
+/// A [`BlockSource`] backed by any node exposing `eth_getBlockByNumber`.
+///
+/// Works with any chain whose transactions implement [`PayloadTransaction`], such as optimism.
+#[derive(Debug, Clone)]
+pub struct RpcBlockSource {
+    client: HttpClient,
+}
+
+impl RpcBlockSource {
+    /// Creates a [`Self`] connected to the `url` JSON-RPC endpoint.
+    pub fn new(url: &str) -> Result<Self, eyre::Error> {
+        Ok(Self { client: HttpClientBuilder::default().build(url)? })
+    }
+}
+
+impl<T: PayloadTransaction> BlockSource<T> for RpcBlockSource {
+    fn latest(&self) -> BoxFuture<'_, eyre::Result<BlockNumber>> {
+        Box::pin(async move {
+            Ok(EthApiClient::<T, Block<T>, Receipt>::block_number(&self.client)
+                .await?
+                .try_into()?)
+        })
+    }
+
+    fn block(&self, number: BlockNumber) -> BoxFuture<'_, eyre::Result<Block<T>>> {
+        Box::pin(async move {
+            EthApiClient::<T, Block<T>, Receipt>::block_by_number(&self.client, number.into(), true)
+                .await?
+                .ok_or_else(|| eyre::eyre!("block {number} not found on rpc source"))
+        })
+    }
+
+    fn tagged(&self, tag: BlockNumberOrTag) -> BoxFuture<'_, eyre::Result<Option<BlockNumber>>> {
+        // Only the header is decoded, transactions being returned as hashes
+        Box::pin(async move {
+            let header: Option<Header> =
+                self.client.request("eth_getBlockByNumber", rpc_params![tag, false]).await?;
+            Ok(header.map(|header| header.number))
+        })
+    }
+
+    fn logs(
+        &self,
+        number: BlockNumber,
+        addresses: Vec<Address>,
+    ) -> BoxFuture<'_, eyre::Result<Vec<Log>>> {
+        Box::pin(async move {
+            let filter = Filter::new().from_block(number).to_block(number).address(addresses);
+            let logs: Vec<RpcLog> = self.client.request("eth_getLogs", rpc_params![filter]).await?;
+            Ok(logs.into_iter().map(|log| log.inner).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{Bytes, U64};
+    use jsonrpsee::{
+        server::{Server, ServerHandle},
+        types::ErrorObjectOwned,
+        RpcModule,
+    };
+    use reth::rpc::types::{BlockNumberOrTag, Transaction};
+    use std::collections::BTreeMap;
+
+    /// Block the mock node tags as finalized.
+    const FINALIZED: BlockNumber = 2;
+
+    /// Returns an empty block at `number`.
+    fn block(number: BlockNumber) -> Block {
+        let mut block = Block::<Transaction>::default();
+        block.header.inner.number = number;
+        block.header.hash = block.header.inner.hash_slow();
+        block
+    }
+
+    /// Serves empty blocks 1 to 3 over JSON-RPC, and returns a source querying them.
+    async fn mock() -> (RpcBlockSource, ServerHandle) {
+        let blocks: BTreeMap<BlockNumber, Block> =
+            (1..=3).map(|number| (number, block(number))).collect();
+
+        let mut module = RpcModule::new(blocks);
+        module
+            .register_method("eth_blockNumber", |_, blocks, _| {
+                Ok::<_, ErrorObjectOwned>(U64::from(*blocks.last_key_value().expect("not empty").0))
+            })
+            .unwrap();
+        module
+            .register_method("eth_getBlockByNumber", |params, blocks, _| {
+                let (tag, _full): (BlockNumberOrTag, bool) = params.parse()?;
+                let number = match tag {
+                    BlockNumberOrTag::Number(number) => number,
+                    BlockNumberOrTag::Finalized => FINALIZED,
+                    _ => return Ok(None),
+                };
+                Ok::<_, ErrorObjectOwned>(blocks.get(&number).cloned())
+            })
+            .unwrap();
+        module
+            .register_method("eth_getLogs", |params, _, _| {
+                // Every address emits an empty log in every block
+                let filter: Filter = params.one()?;
+                let logs = filter
+                    .address
+                    .iter()
+                    .map(|address| RpcLog {
+                        inner: Log::new_unchecked(*address, Vec::new(), Bytes::new()),
+                        block_number: filter.get_from_block(),
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>();
+                Ok::<_, ErrorObjectOwned>(logs)
+            })
+            .unwrap();
+
+        let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        (RpcBlockSource::new(&format!("http://{addr}")).unwrap(), server.start(module))
+    }
+
+    #[tokio::test]
+    async fn fetches_blocks() {
+        let (source, _server) = mock().await;
+        let source: &dyn BlockSource = &source;
+
+        assert_eq!(source.latest().await.unwrap(), 3);
+
+        let block = source.block(2).await.unwrap();
+        assert_eq!(block.header.number, 2);
+        assert_eq!(block.header.hash, block.header.inner.hash_slow());
+
+        let err = source.block(4).await.unwrap_err();
+        assert_eq!(err.to_string(), "block 4 not found on rpc source");
+    }
+
+    #[tokio::test]
+    async fn fetches_logs() {
+        let (source, _server) = mock().await;
+        let source: &dyn BlockSource = &source;
+
+        let address = Address::repeat_byte(0x01);
+        let logs = source.logs(2, vec![address]).await.unwrap();
+        assert_eq!(logs, vec![Log::new_unchecked(address, Vec::new(), Bytes::new())]);
+    }
+
+    #[tokio::test]
+    async fn resolves_tags() {
+        let (source, _server) = mock().await;
+        let source: &dyn BlockSource = &source;
+
+        assert_eq!(source.tagged(BlockNumberOrTag::Finalized).await.unwrap(), Some(FINALIZED));
+        assert_eq!(source.tagged(BlockNumberOrTag::Safe).await.unwrap(), None);
+    }
+}
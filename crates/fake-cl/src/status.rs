@@ -0,0 +1,220 @@
+use crate::{differential::Divergence, mutation::MutationOutcome};
+use alloy_primitives::{BlockHash, BlockNumber};
+use reth::rpc::types::engine::{PayloadStatus, PayloadStatusEnum};
+use serde::Serialize;
+use std::{str::FromStr, time::Duration};
+
+/// Delay between resubmissions of a payload that was not accepted as `VALID`.
+pub(crate) const RESUBMIT_DELAY: Duration = Duration::from_secs(1);
+
+/// Default number of resubmissions for [`StatusPolicy::Retry`].
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Default number of resubmissions for [`StatusPolicy::WaitForSync`], about ten minutes with
+/// [`RESUBMIT_DELAY`].
+const DEFAULT_SYNC_RETRIES: u32 = 600;
+
+/// What to do when `newPayload` or `forkchoiceUpdated` do not return `VALID`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatusPolicy {
+    /// Stops on the first non `VALID` status.
+    #[default]
+    FailFast,
+    /// Resubmits up to the given number of times on any non `VALID` status.
+    Retry(u32),
+    /// Resubmits until the node is done syncing, up to the given number of times. Stops on
+    /// `INVALID`.
+    WaitForSync(u32),
+}
+
+impl StatusPolicy {
+    /// Whether a payload should be resubmitted after `attempts` tries resulted in `outcome`.
+    pub(crate) fn should_resubmit(&self, outcome: &PayloadOutcome, attempts: u32) -> bool {
+        match self {
+            Self::FailFast => false,
+            Self::Retry(retries) => attempts <= *retries,
+            Self::WaitForSync(retries) => !outcome.is_invalid() && attempts <= *retries,
+        }
+    }
+}
+
+impl FromStr for StatusPolicy {
+    type Err = String;
+
+    /// Parses `fail-fast`, `retry`, `retry:<N>`, `wait-for-sync` or `wait-for-sync:<N>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail-fast" => Ok(Self::FailFast),
+            "retry" => Ok(Self::Retry(DEFAULT_RETRIES)),
+            "wait-for-sync" => Ok(Self::WaitForSync(DEFAULT_SYNC_RETRIES)),
+            _ => s
+                .strip_prefix("retry:")
+                .and_then(|retries| retries.parse().ok())
+                .map(Self::Retry)
+                .or_else(|| {
+                    s.strip_prefix("wait-for-sync:")
+                        .and_then(|retries| retries.parse().ok())
+                        .map(Self::WaitForSync)
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "invalid policy {s}: expected fail-fast, retry[:N] or wait-for-sync[:N]"
+                    )
+                }),
+        }
+    }
+}
+
+/// Result of submitting a block through `newPayload` and `forkchoiceUpdated`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PayloadOutcome {
+    /// Block number of the payload.
+    pub block_number: BlockNumber,
+    /// Block hash of the payload.
+    pub block_hash: BlockHash,
+    /// Status returned by `newPayload`.
+    pub new_payload: PayloadStatus,
+    /// Status returned by `forkchoiceUpdated`. Not issued if the payload is `INVALID`.
+    pub forkchoice_updated: Option<PayloadStatus>,
+    /// Latency of the last `newPayload` request.
+    pub new_payload_latency: Duration,
+    /// Latency of the last `forkchoiceUpdated` request, if issued.
+    pub forkchoice_updated_latency: Option<Duration>,
+    /// Gas used by the block.
+    pub gas_used: u64,
+    /// Number of transactions in the block.
+    pub transaction_count: u64,
+    /// Number of times the payload was submitted.
+    pub attempts: u32,
+    /// Outcomes of the corrupted variants submitted before the payload.
+    pub mutations: Vec<MutationOutcome>,
+    /// Statuses returned by peers that differ from the driven node.
+    pub divergences: Vec<Divergence>,
+}
+
+impl PayloadOutcome {
+    /// Whether the forkchoice update is `VALID`, which implies the payload is valid even if the
+    /// node was still syncing when it received it.
+    pub fn is_valid(&self) -> bool {
+        !self.new_payload.status.is_invalid() &&
+            self.forkchoice_updated.as_ref().is_some_and(|status| status.status.is_valid())
+    }
+
+    /// Whether either the payload or the forkchoice update are `INVALID`.
+    pub fn is_invalid(&self) -> bool {
+        self.new_payload.status.is_invalid() ||
+            self.forkchoice_updated.as_ref().is_some_and(|status| status.status.is_invalid())
+    }
+
+    /// Returns an error describing the non `VALID` status, with its `latestValidHash` and
+    /// `validationError`, or the first corrupted variant that was not rejected.
+    pub fn ensure_valid(&self) -> Result<(), eyre::Error> {
+        if self.is_valid() {
+            return match self.mutations.iter().find(|mutation| !mutation.is_rejected()) {
+                Some(mutation) => Err(eyre::eyre!(
+                    "newPayload for corrupted block {} ({:?}) returned {:?}. latestValidHash: {:?}, expected: {:?}",
+                    self.block_number,
+                    mutation.mutation,
+                    mutation.status.status,
+                    mutation.status.latest_valid_hash,
+                    mutation.expected_latest_valid_hash
+                )),
+                None => Ok(()),
+            }
+        }
+
+        let (method, status) = match &self.forkchoice_updated {
+            Some(status) if !self.new_payload.status.is_invalid() => ("forkchoiceUpdated", status),
+            _ => ("newPayload", &self.new_payload),
+        };
+        let validation_error = match &status.status {
+            PayloadStatusEnum::Invalid { validation_error } => Some(validation_error.as_str()),
+            _ => None,
+        };
+
+        Err(eyre::eyre!(
+            "{method} for block {} ({}) returned {:?} after {} attempts. latestValidHash: {:?}, validationError: {:?}",
+            self.block_number,
+            self.block_hash,
+            status.status,
+            self.attempts,
+            status.latest_valid_hash,
+            validation_error
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(status: PayloadStatusEnum) -> PayloadOutcome {
+        PayloadOutcome {
+            block_number: 1,
+            block_hash: BlockHash::ZERO,
+            new_payload: PayloadStatus::from_status(status),
+            forkchoice_updated: None,
+            new_payload_latency: Duration::ZERO,
+            forkchoice_updated_latency: None,
+            gas_used: 0,
+            transaction_count: 0,
+            attempts: 1,
+            mutations: Vec::new(),
+            divergences: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("fail-fast".parse::<StatusPolicy>(), Ok(StatusPolicy::FailFast));
+        assert_eq!("retry".parse::<StatusPolicy>(), Ok(StatusPolicy::Retry(DEFAULT_RETRIES)));
+        assert_eq!("retry:5".parse::<StatusPolicy>(), Ok(StatusPolicy::Retry(5)));
+        assert_eq!(
+            "wait-for-sync".parse::<StatusPolicy>(),
+            Ok(StatusPolicy::WaitForSync(DEFAULT_SYNC_RETRIES))
+        );
+        assert_eq!("wait-for-sync:10".parse::<StatusPolicy>(), Ok(StatusPolicy::WaitForSync(10)));
+        assert!("retry:".parse::<StatusPolicy>().is_err());
+        assert!("retry:-1".parse::<StatusPolicy>().is_err());
+        assert!("ignore".parse::<StatusPolicy>().is_err());
+    }
+
+    #[test]
+    fn resubmits_according_to_policy() {
+        let syncing = outcome(PayloadStatusEnum::Syncing);
+        let invalid =
+            outcome(PayloadStatusEnum::Invalid { validation_error: "bad state root".to_string() });
+
+        assert!(!StatusPolicy::FailFast.should_resubmit(&syncing, 1));
+
+        assert!(StatusPolicy::Retry(2).should_resubmit(&invalid, 1));
+        assert!(StatusPolicy::Retry(2).should_resubmit(&invalid, 2));
+        assert!(!StatusPolicy::Retry(2).should_resubmit(&invalid, 3));
+
+        assert!(StatusPolicy::WaitForSync(100).should_resubmit(&syncing, 100));
+        assert!(!StatusPolicy::WaitForSync(100).should_resubmit(&syncing, 101));
+        assert!(!StatusPolicy::WaitForSync(100).should_resubmit(&invalid, 1));
+    }
+
+    #[test]
+    fn requires_valid_forkchoice_update() {
+        let mut outcome = outcome(PayloadStatusEnum::Valid);
+        assert!(!outcome.is_valid());
+        assert!(outcome.ensure_valid().unwrap_err().to_string().contains("newPayload"));
+
+        outcome.forkchoice_updated = Some(PayloadStatus::from_status(PayloadStatusEnum::Valid));
+        assert!(outcome.is_valid());
+        outcome.ensure_valid().unwrap();
+    }
+
+    #[test]
+    fn syncing_payloads_are_valid_once_forkchoice_updated() {
+        let mut outcome = outcome(PayloadStatusEnum::Syncing);
+        outcome.forkchoice_updated = Some(PayloadStatus::from_status(PayloadStatusEnum::Syncing));
+        assert!(!outcome.is_valid());
+        assert!(outcome.ensure_valid().unwrap_err().to_string().contains("forkchoiceUpdated"));
+
+        outcome.forkchoice_updated = Some(PayloadStatus::from_status(PayloadStatusEnum::Valid));
+        assert!(outcome.is_valid());
+    }
+}
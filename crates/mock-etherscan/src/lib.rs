@@ -0,0 +1,385 @@
+use axum::{
+    extract::{Query, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::debug;
+
+/// Failures injected by [`MockEtherscan`], each one every `N` requests.
+///
+/// Requests are counted from one, so `Some(3)` fails the 3rd, 6th, 9th... request. When several
+/// failures apply to the same request, the first one in field order wins.
+#[derive(Debug, Clone, Default)]
+pub struct Failures {
+    /// Answers with a rate limit: HTTP `429` with `Retry-After` if `retry_after` is set,
+    /// otherwise etherscan's `NOTOK` result.
+    pub rate_limit_every: Option<u64>,
+    /// Seconds sent as `Retry-After` on rate limits.
+    pub retry_after: Option<u64>,
+    /// Waits for `timeout` before answering.
+    pub timeout_every: Option<u64>,
+    /// How long timed out requests hang.
+    pub timeout: Duration,
+    /// Answers with a truncated JSON body.
+    pub malformed_every: Option<u64>,
+    /// Answers with etherscan's `NOTOK` result for an invalid api key.
+    pub api_error_every: Option<u64>,
+    /// Answers with a JSON-RPC error forwarded from the node.
+    pub rpc_error_every: Option<u64>,
+}
+
+/// Mock of the etherscan `proxy` module, serving `eth_getBlockByNumber` and `eth_blockNumber`
+/// from fixture blocks, and of the `logs` module, serving `getLogs` from fixture logs.
+#[derive(Debug, Clone)]
+pub struct MockEtherscan {
+    state: Arc<MockState>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    /// Fixture blocks, as returned by `eth_getBlockByNumber` with full transactions.
+    blocks: BTreeMap<u64, Value>,
+    /// Fixture logs of each block, as returned by `getLogs`.
+    logs: BTreeMap<u64, Vec<Value>>,
+    failures: Failures,
+    /// Number of requests received so far.
+    requests: AtomicU64,
+}
+
+/// Query parameters of an etherscan API request.
+#[derive(Debug, Deserialize)]
+struct ApiQuery {
+    module: Option<String>,
+    action: Option<String>,
+    tag: Option<String>,
+    boolean: Option<bool>,
+    #[serde(rename = "fromBlock")]
+    from_block: Option<u64>,
+    address: Option<String>,
+}
+
+impl MockEtherscan {
+    /// Creates a [`Self`] serving `blocks`, keyed by block number.
+    pub fn new(blocks: BTreeMap<u64, Value>) -> Self {
+        Self {
+            state: Arc::new(MockState {
+                blocks,
+                logs: BTreeMap::new(),
+                failures: Failures::default(),
+                requests: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Loads every `<block_number>.json` fixture of `dir`, the layout of a `--block-cache` chain
+    /// directory.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, eyre::Error> {
+        let mut blocks = BTreeMap::new();
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue
+            }
+            let Some(number) = path.file_stem().and_then(|stem| stem.to_str()?.parse().ok()) else {
+                continue
+            };
+            blocks.insert(number, serde_json::from_slice(&std::fs::read(&path)?)?);
+        }
+
+        if blocks.is_empty() {
+            eyre::bail!("no block fixtures found in {}", dir.as_ref().display())
+        }
+        Ok(Self::new(blocks))
+    }
+
+    /// Sets the injected [`Failures`].
+    pub fn with_failures(self, failures: Failures) -> Self {
+        let state = Arc::into_inner(self.state).expect("not shared before serving");
+        Self { state: Arc::new(MockState { failures, ..state }) }
+    }
+
+    /// Sets the logs served by `getLogs`, keyed by block number.
+    pub fn with_logs(self, logs: BTreeMap<u64, Vec<Value>>) -> Self {
+        let state = Arc::into_inner(self.state).expect("not shared before serving");
+        Self { state: Arc::new(MockState { logs, ..state }) }
+    }
+
+    /// Returns the number of requests received so far.
+    pub fn requests(&self) -> u64 {
+        self.state.requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the [`Router`] serving the API on both `/` and `/api`.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/", get(handle))
+            .route("/api", get(handle))
+            .with_state(self.state.clone())
+    }
+
+    /// Serves the API on `addr` in a background task. Returns the bound address, which is
+    /// useful with port `0`, alongside the task handle.
+    pub async fn spawn(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<(SocketAddr, JoinHandle<std::io::Result<()>>), eyre::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let router = self.router();
+        Ok((addr, tokio::spawn(async move { axum::serve(listener, router).await })))
+    }
+}
+
+/// Answers a single etherscan API request.
+async fn handle(State(state): State<Arc<MockState>>, Query(query): Query<ApiQuery>) -> Response {
+    let request = state.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    let failures = &state.failures;
+    let fails = |every: Option<u64>| every.is_some_and(|every| every > 0 && request % every == 0);
+
+    if fails(failures.rate_limit_every) {
+        debug!(request, "Injecting rate limit");
+        return match failures.retry_after {
+            Some(retry_after) => {
+                (StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, retry_after.to_string())])
+                    .into_response()
+            }
+            None => etherscan_error("Max rate limit reached"),
+        }
+    }
+    if fails(failures.timeout_every) {
+        debug!(request, "Injecting timeout");
+        tokio::time::sleep(failures.timeout).await;
+    }
+    if fails(failures.malformed_every) {
+        debug!(request, "Injecting malformed response");
+        return (StatusCode::OK, r#"{"jsonrpc":"2.0","id":1,"result":{"#).into_response()
+    }
+    if fails(failures.api_error_every) {
+        debug!(request, "Injecting api error");
+        return etherscan_error("Invalid API Key")
+    }
+    if fails(failures.rpc_error_every) {
+        debug!(request, "Injecting JSON-RPC error");
+        return rpc_error(-32000, "header not found")
+    }
+
+    match query.module.as_deref() {
+        Some("proxy") => {}
+        Some("logs") if query.action.as_deref() == Some("getLogs") => return logs(&state, &query),
+        Some("logs") => return etherscan_error("Error! Invalid action name"),
+        _ => return etherscan_error("Error! Missing Or invalid Module name"),
+    }
+
+    let result = match query.action.as_deref() {
+        Some("eth_blockNumber") => {
+            json!(format!("{:#x}", state.blocks.last_key_value().map_or(0, |(number, _)| *number)))
+        }
+        Some("eth_getBlockByNumber") => {
+            let number = match query.tag.as_deref() {
+                Some("earliest") => state.blocks.first_key_value().map(|(number, _)| *number),
+                Some("latest" | "safe" | "finalized" | "pending") => {
+                    state.blocks.last_key_value().map(|(number, _)| *number)
+                }
+                Some(tag) => {
+                    tag.strip_prefix("0x").and_then(|number| u64::from_str_radix(number, 16).ok())
+                }
+                None => None,
+            };
+            let Some(number) = number else { return rpc_error(-32602, "invalid block tag") };
+
+            match state.blocks.get(&number) {
+                Some(block) if query.boolean == Some(false) => hashes_only(block),
+                Some(block) => block.clone(),
+                None => Value::Null,
+            }
+        }
+        _ => return etherscan_error("Error! Invalid action name"),
+    };
+
+    Json(json!({ "jsonrpc": "2.0", "id": 1, "result": result })).into_response()
+}
+
+/// Answers a `getLogs` request with the fixture logs of its block emitted by its address.
+fn logs(state: &MockState, query: &ApiQuery) -> Response {
+    let logs = query
+        .from_block
+        .and_then(|number| state.logs.get(&number))
+        .into_iter()
+        .flatten()
+        .filter(|log| {
+            let address = log.get("address").and_then(Value::as_str);
+            address.zip(query.address.as_deref()).is_some_and(|(a, b)| a.eq_ignore_ascii_case(b))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Etherscan reports empty results with its error status
+    let (status, message) = if logs.is_empty() { ("0", "No records found") } else { ("1", "OK") };
+    Json(json!({ "status": status, "message": message, "result": logs })).into_response()
+}
+
+/// Returns `block` with transaction hashes instead of full transactions.
+fn hashes_only(block: &Value) -> Value {
+    let mut block = block.clone();
+    if let Some(transactions) = block.get_mut("transactions").and_then(Value::as_array_mut) {
+        for tx in transactions {
+            *tx = tx.get("hash").cloned().unwrap_or(Value::Null);
+        }
+    }
+    block
+}
+
+/// Etherscan error body, which is answered with HTTP `200`.
+fn etherscan_error(result: &str) -> Response {
+    Json(json!({ "status": "0", "message": "NOTOK", "result": result })).into_response()
+}
+
+/// JSON-RPC error body forwarded from the node.
+fn rpc_error(code: i64, message: &str) -> Response {
+    Json(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": code, "message": message } }))
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock(failures: Failures) -> MockEtherscan {
+        let block = |number: u64| {
+            json!({
+                "number": format!("{number:#x}"),
+                "transactions": [{ "hash": format!("{:#066x}", number), "nonce": "0x0" }],
+            })
+        };
+        MockEtherscan::new((1..=3).map(|number| (number, block(number))).collect())
+            .with_failures(failures)
+    }
+
+    /// Sends a `proxy` module request for `action`.
+    async fn request(
+        mock: &MockEtherscan,
+        action: &str,
+        tag: Option<&str>,
+        boolean: Option<bool>,
+    ) -> (StatusCode, Value) {
+        let query = ApiQuery {
+            module: Some("proxy".to_string()),
+            action: Some(action.to_string()),
+            tag: tag.map(ToString::to_string),
+            boolean,
+            from_block: None,
+            address: None,
+        };
+        let response = handle(State(mock.state.clone()), Query(query)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_blocks() {
+        let mock = mock(Failures::default());
+
+        let (_, body) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(body["result"], "0x3");
+
+        let (_, body) = request(&mock, "eth_getBlockByNumber", Some("0x2"), Some(true)).await;
+        assert_eq!(body["result"]["number"], "0x2");
+        assert_eq!(body["result"]["transactions"][0]["nonce"], "0x0");
+
+        let (_, body) =
+            request(&mock, "eth_getBlockByNumber", Some("finalized"), Some(false)).await;
+        assert_eq!(body["result"]["number"], "0x3");
+        assert_eq!(body["result"]["transactions"][0], format!("{:#066x}", 3));
+
+        let (_, body) = request(&mock, "eth_getBlockByNumber", Some("0x10"), Some(true)).await;
+        assert_eq!(body["result"], Value::Null);
+
+        let (_, body) = request(&mock, "eth_getBlockByNumber", Some("next"), Some(true)).await;
+        assert_eq!(body["error"]["code"], -32602);
+
+        assert_eq!(mock.requests(), 5);
+    }
+
+    #[tokio::test]
+    async fn injects_failures_periodically() {
+        let mock = mock(Failures {
+            rate_limit_every: Some(2),
+            retry_after: Some(1),
+            ..Default::default()
+        });
+
+        let (status, _) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let (status, _) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mock = self::mock(Failures {
+            api_error_every: Some(1),
+            rpc_error_every: Some(1),
+            ..Default::default()
+        });
+        let (status, body) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "0", "message": "NOTOK", "result": "Invalid API Key" }));
+
+        let mock = self::mock(Failures { malformed_every: Some(1), ..Default::default() });
+        let (status, body) = request(&mock, "eth_blockNumber", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_logs() {
+        let log = json!({ "address": "0x00000000219ab540356cbb839cbe05303d7705fa", "topics": [] });
+        let mock = mock(Failures::default()).with_logs(BTreeMap::from([(2, vec![log.clone()])]));
+        let request = |from_block, address: &str| {
+            let query = ApiQuery {
+                module: Some("logs".to_string()),
+                action: Some("getLogs".to_string()),
+                tag: None,
+                boolean: None,
+                from_block: Some(from_block),
+                address: Some(address.to_string()),
+            };
+            let state = mock.state.clone();
+            async move {
+                let response = handle(State(state), Query(query)).await;
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        let body = request(2, "0x00000000219AB540356cBB839Cbe05303d7705Fa").await;
+        assert_eq!(body, json!({ "status": "1", "message": "OK", "result": [log] }));
+
+        let empty = json!({ "status": "0", "message": "No records found", "result": [] });
+        assert_eq!(request(1, "0x00000000219ab540356cbb839cbe05303d7705fa").await, empty);
+        assert_eq!(request(2, "0x0000000000000000000000000000000000000001").await, empty);
+    }
+
+    #[test]
+    fn requires_fixtures() {
+        let dir = std::env::temp_dir().join(format!("mock-etherscan-{}-empty", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(MockEtherscan::from_dir(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use clap::Parser;
+use mock_etherscan::{Failures, MockEtherscan};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+use tracing::info;
+
+#[derive(Debug, Parser)]
+#[command(about = "Serves the etherscan proxy module from block fixtures, with injected failures")]
+pub struct CliArgs {
+    /// Directory of `<block_number>.json` fixtures, eg. `<DIR>/<chain_id>` recorded with
+    /// `--block-cache`.
+    #[arg(long, value_name = "DIR")]
+    pub fixtures: PathBuf,
+
+    /// Address to listen on.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8590")]
+    pub addr: SocketAddr,
+
+    /// Answers every `N`th request with a rate limit.
+    #[arg(long, value_name = "N")]
+    pub rate_limit_every: Option<u64>,
+
+    /// Answers rate limits with HTTP `429` and this `Retry-After`, instead of etherscan's `NOTOK`.
+    #[arg(long, value_name = "SECONDS")]
+    pub retry_after: Option<u64>,
+
+    /// Hangs every `N`th request for `--timeout` before answering.
+    #[arg(long, value_name = "N")]
+    pub timeout_every: Option<u64>,
+
+    /// How long timed out requests hang, in seconds.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    pub timeout: u64,
+
+    /// Answers every `N`th request with malformed JSON.
+    #[arg(long, value_name = "N")]
+    pub malformed_every: Option<u64>,
+
+    /// Answers every `N`th request with etherscan's invalid api key error.
+    #[arg(long, value_name = "N")]
+    pub api_error_every: Option<u64>,
+
+    /// Answers every `N`th request with a JSON-RPC error.
+    #[arg(long, value_name = "N")]
+    pub rpc_error_every: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = CliArgs::parse();
+    let mock = MockEtherscan::from_dir(&args.fixtures)?.with_failures(Failures {
+        rate_limit_every: args.rate_limit_every,
+        retry_after: args.retry_after,
+        timeout_every: args.timeout_every,
+        timeout: Duration::from_secs(args.timeout),
+        malformed_every: args.malformed_every,
+        api_error_every: args.api_error_every,
+        rpc_error_every: args.rpc_error_every,
+    });
+
+    let (addr, server) = mock.spawn(args.addr).await?;
+    info!("Serving etherscan mock on http://{addr}/api");
+
+    Ok(server.await??)
+}
@@ -5,44 +5,56 @@ use reth_optimism_cli::{chainspec::OpChainSpecParser, Cli};
 use reth_optimism_node::{node::OpAddOns, OpNode};
 use tester_common::node::{
     args::TestArgs,
-    exex::exex,
+    exex::{exex, wait_for_exit},
     ext::{TesterExt, TesterExtApiServer},
 };
 
 fn main() {
     let (engine_api_handle_tx, engine_api_handle_rx) = tokio::sync::oneshot::channel();
+    let (completion_tx, completion_rx) = tokio::sync::oneshot::channel();
 
-    let rpc_ext = TesterExt::new();
+    let (rpc_ext, control) = TesterExt::new();
     let rpc_status = rpc_ext.watcher.clone();
 
-    Cli::<OpChainSpecParser, TestArgs>::parse()
-        .run(|builder, args| async move {
-            let handle = builder
-                .with_types_and_provider::<OpNode, BlockchainProvider2<_>>()
-                .with_components(OpNode::components(Default::default()))
-                .with_add_ons(OpAddOns::default())
-                .extend_rpc_modules(move |ctx| {
-                    ctx.modules.merge_configured(rpc_ext.into_rpc())?;
-                    Ok(())
-                })
-                .on_rpc_started(|_ctx, handles| {
-                    let _ = engine_api_handle_tx.send((handles.auth.clone(), handles.rpc.clone()));
-                    Ok(())
-                })
-                .install_exex("tester", move |ctx| async move {
-                    Ok(exex(ctx, engine_api_handle_rx, rpc_status, args))
-                })
-                .launch_with_fn(|builder| {
-                    let launcher = EngineNodeLauncher::new(
-                        builder.task_executor().clone(),
-                        builder.config().datadir(),
-                        Default::default(),
-                    );
-                    builder.launch_with(launcher)
-                })
-                .await?;
+    if let Err(err) = Cli::<OpChainSpecParser, TestArgs>::parse().run(|builder, args| async move {
+        let exit_on_complete = args.exit_on_complete;
+        let handle = builder
+            .with_types_and_provider::<OpNode, BlockchainProvider2<_>>()
+            .with_components(OpNode::components(Default::default()))
+            .with_add_ons(OpAddOns::default())
+            .extend_rpc_modules(move |ctx| {
+                ctx.modules.merge_configured(rpc_ext.into_rpc())?;
+                Ok(())
+            })
+            .on_rpc_started(|_ctx, handles| {
+                let _ = engine_api_handle_tx.send((handles.auth.clone(), handles.rpc.clone()));
+                Ok(())
+            })
+            .install_exex("tester", move |ctx| async move {
+                let fake_cl = args.op_fake_cl(ctx.config.chain.as_ref())?;
+                Ok(exex(
+                    ctx,
+                    fake_cl,
+                    engine_api_handle_rx,
+                    rpc_status,
+                    control,
+                    completion_tx,
+                    args,
+                ))
+            })
+            .launch_with_fn(|builder| {
+                let launcher = EngineNodeLauncher::new(
+                    builder.task_executor().clone(),
+                    builder.config().datadir(),
+                    Default::default(),
+                );
+                builder.launch_with(launcher)
+            })
+            .await?;
 
-            handle.wait_for_node_exit().await
-        })
-        .unwrap();
+        wait_for_exit(handle.wait_for_node_exit(), exit_on_complete.then_some(completion_rx)).await
+    }) {
+        eprintln!("Error: {err:?}");
+        std::process::exit(1);
+    }
 }
@@ -7,18 +7,20 @@ use reth::{
 use reth_node_ethereum::{node::EthereumAddOns, EthereumNode};
 use tester_common::node::{
     args::TestArgs,
-    exex::exex,
+    exex::{exex, wait_for_exit},
     ext::{TesterExt, TesterExtApiServer},
 };
 
 fn main() {
     let (engine_api_handle_tx, engine_api_handle_rx) = tokio::sync::oneshot::channel();
+    let (completion_tx, completion_rx) = tokio::sync::oneshot::channel();
 
-    let rpc_ext = TesterExt::new();
+    let (rpc_ext, control) = TesterExt::new();
     let rpc_status = rpc_ext.watcher.clone();
 
-    Cli::<EthereumChainSpecParser, TestArgs>::parse()
-        .run(|builder, args| async move {
+    if let Err(err) =
+        Cli::<EthereumChainSpecParser, TestArgs>::parse().run(|builder, args| async move {
+            let exit_on_complete = args.exit_on_complete;
             let handle = builder
                 .with_types_and_provider::<EthereumNode, BlockchainProvider2<_>>()
                 .with_components(EthereumNode::components())
@@ -32,7 +34,16 @@ fn main() {
                     Ok(())
                 })
                 .install_exex("tester", move |ctx| async move {
-                    Ok(exex(ctx, engine_api_handle_rx, rpc_status, args))
+                    let fake_cl = args.fake_cl(ctx.config.chain.as_ref())?;
+                    Ok(exex(
+                        ctx,
+                        fake_cl,
+                        engine_api_handle_rx,
+                        rpc_status,
+                        control,
+                        completion_tx,
+                        args,
+                    ))
                 })
                 .launch_with_fn(|builder| {
                     let launcher = EngineNodeLauncher::new(
@@ -44,7 +55,11 @@ fn main() {
                 })
                 .await?;
 
-            handle.wait_for_node_exit().await
+            wait_for_exit(handle.wait_for_node_exit(), exit_on_complete.then_some(completion_rx))
+                .await
         })
-        .unwrap();
+    {
+        eprintln!("Error: {err:?}");
+        std::process::exit(1);
+    }
 }
@@ -1,4 +1,23 @@
+use super::{stop::StopCondition, verify::NotificationVerifier};
+use fake_cl::{
+    auth_client,
+    devnet::{Devnet, DevnetConfig, TxMix, DEV_MNEMONIC},
+    differential::Peer,
+    forkchoice::{validate_tag_policies, TagPolicy},
+    mutation::PayloadMutation,
+    payload::{ForkSchedule, OpTransaction, PayloadTransaction},
+    record::Recorder,
+    source::{
+        etherscan_provider, CachedBlockSource, FileBlockSource, RateLimit, RateLimitedBlockSource,
+        RpcBlockSource,
+    },
+    status::StatusPolicy,
+    BlockSource, FakeCl,
+};
+use reth::chainspec::{Chain, EthChainSpec, EthereumHardforks};
 use reth_engine_tree::tree::TreeConfig;
+use reth_tracing::tracing::warn;
+use std::{path::PathBuf, time::Duration};
 
 fn default_persistence() -> u64 {
     TreeConfig::default().persistence_threshold()
@@ -9,10 +28,331 @@ fn default_persistence() -> u64 {
 pub struct TestArgs {
     #[arg(long, value_name = "ETHERSCAN_API_URL")]
     pub etherscan_url: Option<String>,
-    /// Uses etherscan to sync up to `num_blocks`. **Should not** be used with a CL.
+    /// Uses a JSON-RPC node as the block source instead of etherscan.
+    #[arg(long, value_name = "RPC_URL", conflicts_with_all = ["etherscan_url", "rlp_source"])]
+    pub rpc_source: Option<String>,
+    /// Replays post-merge blocks from an RLP-encoded chain file instead of etherscan.
+    #[arg(long, value_name = "PATH", conflicts_with = "etherscan_url")]
+    pub rlp_source: Option<PathBuf>,
+    /// Maximum number of requests per second sent to etherscan or the RPC source.
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    pub source_rps: u32,
+    /// Number of retries, with exponential backoff, of a failed block source request.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub source_retries: u32,
+    /// Number of blocks fetched ahead of the one being submitted.
+    #[arg(long, value_name = "N", default_value_t = 8)]
+    pub prefetch: u64,
+    /// Records every fetched block to this directory and replays them on later runs.
+    ///
+    /// Without any other block source configured, it replays from the cache only.
+    #[arg(long, value_name = "DIR")]
+    pub block_cache: Option<PathBuf>,
+    /// Uses the block source to sync up to `num_blocks`. **Should not** be used with a CL.
     #[arg(long, default_value_t = default_persistence())]
     pub num_blocks: u64,
+    /// Stops once any of these conditions is met, instead of after `num_blocks`: `block:N`,
+    /// `hash:HASH`, `in-memory:N` blocks not persisted yet, `flushes:N` persistence flushes, or
+    /// cumulative `gas:N` and `txs:N`. Can be repeated.
+    #[arg(long, value_name = "CONDITION", conflicts_with = "num_blocks")]
+    pub stop_at: Vec<StopCondition>,
     /// Runs equality tests across many RPCs calls after syncing `num_blocks`.
     #[arg(long)]
     pub against_rpc: Option<String>,
+    /// Shuts the node down once done, after `--against-rpc` if set, exiting with a non-zero
+    /// status code if the run failed.
+    #[arg(long)]
+    pub exit_on_complete: bool,
+    /// What to do when a payload is not `VALID`: `fail-fast`, `retry[:N]` or `wait-for-sync[:N]`.
+    #[arg(long, value_name = "POLICY", default_value = "fail-fast")]
+    pub payload_status_policy: StatusPolicy,
+    /// How the safe block of each forkchoice update is chosen: `fixed` to the block finalized
+    /// at startup, `lag:N` blocks behind the head or `mirror` the source.
+    #[arg(long, value_name = "POLICY", default_value = "fixed")]
+    pub safe_policy: TagPolicy,
+    /// How the finalized block of each forkchoice update is chosen: `fixed` to the block
+    /// finalized at startup, `lag:N` blocks behind the head or `mirror` the source.
+    #[arg(long, value_name = "POLICY", default_value = "fixed")]
+    pub finalized_policy: TagPolicy,
+    /// Submits corrupted variants of each block before it and requires them to be rejected:
+    /// `state-root`, `block-hash`, `parent`, `versioned-hashes` or `parent-beacon-block-root`.
+    #[arg(long, value_name = "MUTATIONS", value_delimiter = ',')]
+    pub mutations: Vec<PayloadMutation>,
+    /// Engine API endpoint of a peer node receiving the same payloads and forkchoice updates,
+    /// whose statuses are compared with the driven node. Can be repeated.
+    #[arg(long, value_name = "URL", requires = "peer_jwt_secret")]
+    pub peer_engine_url: Vec<String>,
+    /// JWT secret of each `--peer-engine-url`, or a single one shared by all of them.
+    #[arg(long, value_name = "PATH")]
+    pub peer_jwt_secret: Vec<PathBuf>,
+    /// Times every engine API call and reports latency percentiles and gas/s once done.
+    /// Requires an offline or cached block source, and fails on cache misses.
+    #[arg(long, conflicts_with = "devnet")]
+    pub bench: bool,
+    /// Records every engine API request sent to the node, with its status and latency, to this
+    /// JSONL file.
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+    /// Number of seconds to wait for the ExEx notification triggered by each engine API call
+    /// before failing.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub notification_timeout: u64,
+    /// Checks the blocks, receipts and logs of every ExEx notification against the submitted
+    /// blocks, and the receipts of `--receipts-rpc` if set.
+    #[arg(long)]
+    pub verify_notifications: bool,
+    /// JSON-RPC endpoint serving `eth_getBlockReceipts` for the source chain. Defaults to
+    /// `--rpc-source`.
+    #[arg(long, value_name = "RPC_URL", requires = "verify_notifications")]
+    pub receipts_rpc: Option<String>,
+    /// Once done, re-executes the blocks of the run with an ExEx backfill job and restarts an ExEx
+    /// from the WAL, comparing both with the live notifications. The WAL is pruned below the
+    /// finalized block, so this requires a fixed `--finalized-policy`.
+    #[arg(long)]
+    pub exex_replay: bool,
+    /// Injects a reorg every `N` blocks, switching back to the source chain right after.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub reorg_every: Option<u64>,
+    /// Number of blocks replaced by each injected reorg.
+    #[arg(long, value_name = "DEPTH", default_value_t = 1, requires = "reorg_every")]
+    pub reorg_depth: u64,
+    /// Builds blocks locally through the node payload builder instead of following a block
+    /// source. Requires the dev accounts to be funded in the genesis.
+    #[arg(long, conflicts_with_all = ["etherscan_url", "rpc_source", "rlp_source", "block_cache", "mutations"])]
+    pub devnet: bool,
+    /// JSON-RPC endpoint of the node, used in devnet mode to submit transactions.
+    #[arg(long, value_name = "RPC_URL", default_value = "http://localhost:8545")]
+    pub devnet_rpc: String,
+    /// Mnemonic of the pre-funded dev accounts.
+    #[arg(long, value_name = "PHRASE", default_value = DEV_MNEMONIC)]
+    pub devnet_mnemonic: String,
+    /// Number of dev accounts derived from the mnemonic.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub devnet_accounts: u32,
+    /// Number of transactions generated for each devnet block.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub devnet_txs_per_block: usize,
+    /// Proportions of generated transactions: `transfers=N,deploys=N,logs=N,reverts=N`.
+    #[arg(long, value_name = "MIX", default_value = "transfers=4,deploys=1,logs=4,reverts=1")]
+    pub devnet_tx_mix: TxMix,
+    /// Milliseconds given to the node payload builder to include the generated transactions
+    /// before fetching each devnet block.
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub devnet_build_time: u64,
+}
+
+impl TestArgs {
+    /// Ids of the arguments relying on the ExEx notifications of an in-process node.
+    pub const EXEX_ONLY_ARGS: &'static [&'static str] =
+        &["notification_timeout", "verify_notifications", "receipts_rpc", "exex_replay"];
+
+    /// Checks the combinations of arguments that clap can't.
+    fn validate(&self) -> eyre::Result<()> {
+        validate_tag_policies(self.safe_policy, self.finalized_policy)?;
+        if self.exex_replay && self.finalized_policy != TagPolicy::Fixed {
+            eyre::bail!(
+                "--exex-replay requires a fixed --finalized-policy: the WAL is pruned below the finalized block"
+            )
+        }
+        Ok(())
+    }
+
+    /// Creates a [`FakeCl`] for `chain_spec` from the configured block source, wrapped by the
+    /// block cache if enabled.
+    pub fn fake_cl(
+        &self,
+        chain_spec: &(impl EthChainSpec + EthereumHardforks),
+    ) -> eyre::Result<FakeCl> {
+        self.validate()?;
+        if self.bench && self.block_cache.is_none() && self.rlp_source.is_none() {
+            eyre::bail!(
+                "--bench requires an offline or cached block source: --block-cache or --rlp-source"
+            )
+        }
+
+        if self.devnet {
+            if self.safe_policy == TagPolicy::Mirror || self.finalized_policy == TagPolicy::Mirror {
+                eyre::bail!("--devnet has no block source to mirror safe and finalized blocks from")
+            }
+            let devnet = Devnet::new(DevnetConfig {
+                rpc_url: self.devnet_rpc.clone(),
+                chain_id: chain_spec.chain().id(),
+                mnemonic: self.devnet_mnemonic.clone(),
+                accounts: self.devnet_accounts,
+                txs_per_block: self.devnet_txs_per_block,
+                tx_mix: self.devnet_tx_mix.clone(),
+                build_time: Duration::from_millis(self.devnet_build_time),
+            })?;
+            return self.with_recorder(
+                FakeCl::devnet(devnet, ForkSchedule::new(chain_spec))
+                    .with_status_policy(self.payload_status_policy)
+                    .with_tag_policies(self.safe_policy, self.finalized_policy)
+                    .with_peers(self.peers()?),
+            )
+        }
+
+        self.build_fake_cl(chain_spec, self.block_source(chain_spec.chain()))
+    }
+
+    /// Creates an optimism [`FakeCl`] for `chain_spec`, which requires `--rpc-source` pointing to
+    /// an L2 node, wrapped by the block cache if enabled.
+    pub fn op_fake_cl(
+        &self,
+        chain_spec: &(impl EthChainSpec + EthereumHardforks),
+    ) -> eyre::Result<FakeCl<OpTransaction>> {
+        self.validate()?;
+        if self.devnet {
+            eyre::bail!("devnet mode is not supported on optimism")
+        }
+        if self.bench && self.block_cache.is_none() {
+            eyre::bail!("--bench requires a cached block source: --block-cache")
+        }
+
+        let source = match &self.rpc_source {
+            Some(url) => Ok(Box::new(RateLimitedBlockSource::new(
+                RpcBlockSource::new(url)?,
+                self.rate_limit(),
+            )) as Box<dyn BlockSource<_>>),
+            None => Err(eyre::eyre!("optimism requires an L2 node as block source: --rpc-source")),
+        };
+        self.build_fake_cl(chain_spec, source)
+    }
+
+    /// Creates a [`FakeCl`] from `source`, wrapped by the block cache if enabled.
+    fn build_fake_cl<T: PayloadTransaction>(
+        &self,
+        chain_spec: &(impl EthChainSpec + EthereumHardforks),
+        source: eyre::Result<Box<dyn BlockSource<T>>>,
+    ) -> eyre::Result<FakeCl<T>> {
+        let fork_schedule = ForkSchedule::new(chain_spec);
+        let Some(dir) = &self.block_cache else {
+            return self.with_recorder(
+                FakeCl::new(source?, fork_schedule)
+                    .with_prefetch(self.prefetch)
+                    .with_status_policy(self.payload_status_policy)
+                    .with_tag_policies(self.safe_policy, self.finalized_policy)
+                    .with_mutations(self.mutations.clone())
+                    .with_peers(self.peers()?),
+            )
+        };
+
+        let inner = match source {
+            // Benchmarks only replay offline, so cache misses fail instead of hitting the network
+            _ if self.bench && self.rlp_source.is_none() => None,
+            Ok(source) => Some(source),
+            Err(err) if !self.has_explicit_source() => {
+                warn!(%err, "Block source unavailable, replaying from block cache only.");
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        self.with_recorder(
+            FakeCl::new(
+                CachedBlockSource::new(dir, chain_spec.chain().id(), inner)?,
+                fork_schedule,
+            )
+            .with_prefetch(self.prefetch)
+            .with_status_policy(self.payload_status_policy)
+            .with_tag_policies(self.safe_policy, self.finalized_policy)
+            .with_mutations(self.mutations.clone())
+            .with_peers(self.peers()?),
+        )
+    }
+
+    /// Attaches a [`Recorder`] to `fake_cl` if `--record` is set.
+    fn with_recorder<T: PayloadTransaction>(&self, fake_cl: FakeCl<T>) -> eyre::Result<FakeCl<T>> {
+        Ok(match &self.record {
+            Some(path) => fake_cl.with_recorder(Recorder::create(path)?),
+            None => fake_cl,
+        })
+    }
+
+    /// Returns the configured [`StopCondition`]s, defaulting to `num_blocks`.
+    pub fn stop_conditions(&self) -> Vec<StopCondition> {
+        if self.stop_at.is_empty() {
+            vec![StopCondition::Blocks(self.num_blocks)]
+        } else {
+            self.stop_at.clone()
+        }
+    }
+
+    /// Creates the [`NotificationVerifier`] if `--verify-notifications` is set.
+    pub fn notification_verifier(&self) -> eyre::Result<Option<NotificationVerifier>> {
+        if !self.verify_notifications {
+            return Ok(None)
+        }
+        let receipts_url = self.receipts_rpc.as_deref().or(self.rpc_source.as_deref());
+        NotificationVerifier::new(receipts_url).map(Some)
+    }
+
+    /// Creates the configured [`BlockSource`]. Defaults to etherscan.
+    fn block_source(&self, chain: Chain) -> eyre::Result<Box<dyn BlockSource>> {
+        if let Some(url) = &self.rpc_source {
+            return Ok(Box::new(RateLimitedBlockSource::new(
+                RpcBlockSource::new(url)?,
+                self.rate_limit(),
+            )))
+        }
+        if let Some(path) = &self.rlp_source {
+            return Ok(Box::new(FileBlockSource::from_rlp(path)?))
+        }
+        Ok(Box::new(RateLimitedBlockSource::new(
+            etherscan_provider(chain, self.etherscan_url.clone())?,
+            self.rate_limit(),
+        )))
+    }
+
+    /// Returns the [`RateLimit`] applied to network block sources.
+    fn rate_limit(&self) -> RateLimit {
+        RateLimit {
+            requests_per_second: self.source_rps,
+            max_retries: self.source_retries,
+            ..Default::default()
+        }
+    }
+
+    /// Connects to every `--peer-engine-url`.
+    fn peers(&self) -> eyre::Result<Vec<Peer>> {
+        if self.peer_jwt_secret.len() > 1 &&
+            self.peer_jwt_secret.len() != self.peer_engine_url.len()
+        {
+            eyre::bail!("expected one --peer-jwt-secret, or one per --peer-engine-url")
+        }
+
+        self.peer_engine_url
+            .iter()
+            .enumerate()
+            .map(|(index, url)| {
+                let jwt_secret =
+                    self.peer_jwt_secret.get(index).unwrap_or(&self.peer_jwt_secret[0]);
+                Ok(Peer { name: url.clone(), client: auth_client(url, jwt_secret)? })
+            })
+            .collect()
+    }
+
+    /// Whether a block source was explicitly configured.
+    fn has_explicit_source(&self) -> bool {
+        self.etherscan_url.is_some() || self.rpc_source.is_some() || self.rlp_source.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn exex_replay_requires_a_fixed_finalized_block() {
+        TestArgs::parse_from(["test", "--exex-replay"]).validate().unwrap();
+        TestArgs::parse_from(["test", "--exex-replay", "--finalized-policy", "fixed"])
+            .validate()
+            .unwrap();
+        TestArgs::parse_from(["test", "--finalized-policy", "lag:2"]).validate().unwrap();
+
+        let err = TestArgs::parse_from(["test", "--exex-replay", "--finalized-policy", "lag:2"])
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("--exex-replay requires a fixed"), "{err}");
+    }
 }
@@ -0,0 +1,233 @@
+use alloy_primitives::BlockNumber;
+use fake_cl::reorg::Reorg;
+use reth_tracing::tracing::info;
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::sync::{mpsc, oneshot};
+
+/// Reply to [`ControlCommand::Reorg`], or why it was not injected.
+pub type ReorgReply = oneshot::Sender<Result<ReorgReport, String>>;
+
+/// A reorg injected through [`ControlCommand::Reorg`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorgReport {
+    /// The competing branch, with the old and new head hashes.
+    #[serde(flatten)]
+    pub reorg: Reorg,
+    /// Whether the source chain was made canonical again right after.
+    pub switched_back: bool,
+}
+
+/// A command sent by the `tester` RPC namespace to the loop moving the chain forward.
+#[derive(Debug)]
+pub enum ControlCommand {
+    /// Submits `blocks` more blocks, then pauses and replies with the new tip.
+    Advance { blocks: u64, reply: oneshot::Sender<BlockNumber> },
+    /// Pauses after the block being submitted, and replies with the tip.
+    Pause { reply: oneshot::Sender<BlockNumber> },
+    /// Resumes moving the chain forward until a stop condition is met.
+    Resume,
+    /// Replaces the stop conditions by reaching this block number.
+    SetTarget(BlockNumber),
+    /// Stops moving the chain forward and completes the run.
+    Stop,
+    /// Makes a competing branch replacing the last `depth` blocks canonical, and switches back to
+    /// the source chain right after if `switch_back`.
+    Reorg { depth: u64, switch_back: bool, reply: ReorgReply },
+}
+
+/// State of the loop moving the chain forward, driven by [`ControlCommand`]s.
+#[derive(Debug)]
+pub struct Controller {
+    commands: mpsc::UnboundedReceiver<ControlCommand>,
+    paused: bool,
+    stopped: bool,
+    /// Block number to reach before pausing, and who to reply to once reached.
+    step: Option<(BlockNumber, oneshot::Sender<BlockNumber>)>,
+    /// Block number set by [`ControlCommand::SetTarget`], not applied yet.
+    target: Option<BlockNumber>,
+    /// Reorgs requested through [`ControlCommand::Reorg`], not injected yet.
+    reorgs: VecDeque<(u64, bool, ReorgReply)>,
+}
+
+impl Controller {
+    /// Creates a [`Self`] receiving `commands`.
+    pub fn new(commands: mpsc::UnboundedReceiver<ControlCommand>) -> Self {
+        Self {
+            commands,
+            paused: false,
+            stopped: false,
+            step: None,
+            target: None,
+            reorgs: VecDeque::new(),
+        }
+    }
+
+    /// Whether the chain should not move forward until another command is received.
+    pub fn is_paused(&self) -> bool {
+        self.paused && self.step.is_none()
+    }
+
+    /// Whether [`ControlCommand::Stop`] was received.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Whether a [`ControlCommand::Advance`] is in progress, which overrides stop conditions.
+    pub fn is_stepping(&self) -> bool {
+        self.step.is_some()
+    }
+
+    /// Returns the target set by [`ControlCommand::SetTarget`] since the last call, if any.
+    pub fn take_target(&mut self) -> Option<BlockNumber> {
+        self.target.take()
+    }
+
+    /// Returns the next reorg requested by [`ControlCommand::Reorg`], if any.
+    pub fn take_reorg(&mut self) -> Option<(u64, bool, ReorgReply)> {
+        self.reorgs.pop_front()
+    }
+
+    /// Applies the commands received so far, with the chain at `local_tip`.
+    pub fn poll(&mut self, local_tip: BlockNumber) {
+        while let Ok(command) = self.commands.try_recv() {
+            self.apply(command, local_tip);
+        }
+        self.on_tip(local_tip);
+    }
+
+    /// Waits for the next command and applies it, with the chain at `local_tip`.
+    ///
+    /// Never returns once the RPC namespace is dropped, since the chain can't move anymore.
+    pub async fn wait(&mut self, local_tip: BlockNumber) {
+        match self.commands.recv().await {
+            Some(command) => self.apply(command, local_tip),
+            None => std::future::pending().await,
+        }
+        self.poll(local_tip);
+    }
+
+    fn apply(&mut self, command: ControlCommand, local_tip: BlockNumber) {
+        info!(?command, local_tip, "Received control command");
+        match command {
+            ControlCommand::Advance { blocks, reply } => {
+                self.paused = true;
+                self.step = Some((local_tip + blocks, reply));
+            }
+            ControlCommand::Pause { reply } => {
+                self.paused = true;
+                self.step = None;
+                let _ = reply.send(local_tip);
+            }
+            ControlCommand::Resume => {
+                self.paused = false;
+                self.step = None;
+            }
+            ControlCommand::SetTarget(target) => self.target = Some(target),
+            ControlCommand::Stop => self.stopped = true,
+            ControlCommand::Reorg { depth, switch_back, reply } => {
+                self.reorgs.push_back((depth, switch_back, reply))
+            }
+        }
+    }
+
+    /// Replies to the current [`ControlCommand::Advance`] once `local_tip` reaches its target.
+    fn on_tip(&mut self, local_tip: BlockNumber) {
+        if self.step.as_ref().is_some_and(|(target, _)| local_tip >= *target) {
+            let (_, reply) = self.step.take().expect("some");
+            let _ = reply.send(local_tip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> (Controller, mpsc::UnboundedSender<ControlCommand>) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        (Controller::new(receiver), commands)
+    }
+
+    #[test]
+    fn pauses_and_resumes() {
+        let (mut controller, commands) = controller();
+        controller.poll(10);
+        assert!(!controller.is_paused());
+
+        let (reply, mut tip) = oneshot::channel();
+        commands.send(ControlCommand::Pause { reply }).unwrap();
+        controller.poll(10);
+        assert!(controller.is_paused());
+        assert_eq!(tip.try_recv(), Ok(10));
+
+        commands.send(ControlCommand::Resume).unwrap();
+        controller.poll(10);
+        assert!(!controller.is_paused());
+    }
+
+    #[test]
+    fn advances_then_pauses() {
+        let (mut controller, commands) = controller();
+
+        let (reply, mut tip) = oneshot::channel();
+        commands.send(ControlCommand::Advance { blocks: 2, reply }).unwrap();
+        controller.poll(10);
+        assert!(controller.is_stepping());
+        assert!(!controller.is_paused());
+
+        controller.poll(11);
+        assert!(tip.try_recv().is_err());
+
+        controller.poll(12);
+        assert_eq!(tip.try_recv(), Ok(12));
+        assert!(!controller.is_stepping());
+        assert!(controller.is_paused());
+    }
+
+    #[test]
+    fn pausing_cancels_advance() {
+        let (mut controller, commands) = controller();
+
+        let (reply, mut advanced) = oneshot::channel();
+        commands.send(ControlCommand::Advance { blocks: 5, reply }).unwrap();
+        let (reply, mut paused) = oneshot::channel();
+        commands.send(ControlCommand::Pause { reply }).unwrap();
+        controller.poll(10);
+
+        assert_eq!(paused.try_recv(), Ok(10));
+        assert!(matches!(advanced.try_recv(), Err(oneshot::error::TryRecvError::Closed)));
+        assert!(controller.is_paused());
+    }
+
+    #[test]
+    fn applies_targets_and_stop() {
+        let (mut controller, commands) = controller();
+
+        commands.send(ControlCommand::SetTarget(20)).unwrap();
+        controller.poll(10);
+        assert_eq!(controller.take_target(), Some(20));
+        assert_eq!(controller.take_target(), None);
+        assert!(!controller.is_stopped());
+
+        commands.send(ControlCommand::Stop).unwrap();
+        controller.poll(10);
+        assert!(controller.is_stopped());
+    }
+
+    #[test]
+    fn queues_reorgs_in_order() {
+        let (mut controller, commands) = controller();
+
+        let (reply, _first) = oneshot::channel();
+        commands.send(ControlCommand::Reorg { depth: 1, switch_back: false, reply }).unwrap();
+        let (reply, _second) = oneshot::channel();
+        commands.send(ControlCommand::Reorg { depth: 3, switch_back: true, reply }).unwrap();
+        controller.poll(10);
+
+        assert!(matches!(controller.take_reorg(), Some((1, false, _))));
+        assert!(matches!(controller.take_reorg(), Some((3, true, _))));
+        assert!(controller.take_reorg().is_none());
+        assert!(!controller.is_paused());
+    }
+}
@@ -1,120 +1,448 @@
-use super::{args::TestArgs, ext::TesterStatus};
+use super::{
+    args::TestArgs,
+    control::{ControlCommand, Controller, ReorgReport},
+    ext::TesterStatus,
+    notifications::{NotificationKey, NotificationTracker},
+    replay::{check_backfill, check_wal_restart, ReplayStatus},
+    stop::StopTracker,
+    verify::NotificationVerifier,
+};
 use crate::rpc::equality::RpcTester;
-use fake_cl::FakeCl;
-use futures::TryStreamExt;
-use jsonrpsee::http_client::HttpClientBuilder;
+use alloy_eips::BlockNumHash;
+use alloy_primitives::BlockHash;
+use fake_cl::{
+    payload::PayloadTransaction,
+    reorg::{Reorg, MAX_REORG_DEPTH},
+    runner::Runner,
+    FakeCl,
+};
+use futures::future::{self, Either};
+use jsonrpsee::http_client::{transport::HttpBackend, HttpClient, HttpClientBuilder};
 use parking_lot::RwLock;
 use reth::{
-    api::{FullNodeComponents, NodeTypesWithEngine},
-    chainspec::EthChainSpec,
-    providers::{BlockIdReader, BlockNumReader, StageCheckpointReader},
-    rpc::{
-        builder::{auth::AuthServerHandle, RpcServerHandle},
-        types::BlockNumberOrTag,
-    },
+    api::{EngineTypes, FullNodeComponents, NodeTypesWithEngine},
+    providers::{BlockHashReader, BlockIdReader, BlockNumReader, StageCheckpointReader},
+    rpc::builder::{auth::AuthServerHandle, RpcServerHandle},
 };
-use reth_exex::{ExExContext, ExExEvent, ExExNotification};
+use reth_exex::{ExExContext, ExExNotification};
+use reth_rpc_layer::AuthClientService;
 use reth_stages_types::StageId;
-use reth_tracing::tracing::info;
-use std::sync::Arc;
-use tokio::sync::oneshot;
+use reth_tracing::tracing::{info, warn};
+use std::{future::Future, path::Path, pin::pin, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, oneshot};
+
+/// Uses a [`FakeCl`] to move the chain forward **until** one of its stop conditions is met.
+///
+/// With `--exit-on-complete`, the result of the run is sent to `completion` instead of crashing
+/// the node on failure, so it can be shut down cleanly. Either way, the node keeps running until
+/// then, since exiting would crash it.
+pub async fn exex<Node: FullNodeComponents, T: PayloadTransaction>(
+    ctx: ExExContext<Node>,
+    fake_cl: FakeCl<T>,
+    server_receiver: oneshot::Receiver<(AuthServerHandle, RpcServerHandle)>,
+    rpc_status: Arc<RwLock<TesterStatus>>,
+    control: mpsc::UnboundedReceiver<ControlCommand>,
+    completion: oneshot::Sender<eyre::Result<()>>,
+    args: TestArgs,
+) -> eyre::Result<()> {
+    let exit_on_complete = args.exit_on_complete;
+    let ExExContext { components, config, events, notifications, .. } = ctx;
+    let wal_dir = config.datadir().exex_wal();
+    // The WAL is pruned below the finalized block, which `--exex-replay` keeps fixed
+    let finalized = components.provider().finalized_block_number()?.unwrap_or_default();
+    let notifications = NotificationTracker::spawn(
+        notifications,
+        events,
+        rpc_status.clone(),
+        Duration::from_secs(args.notification_timeout),
+        args.exex_replay.then_some(finalized),
+    );
+    let result = run(
+        &components,
+        &notifications,
+        &wal_dir,
+        fake_cl,
+        server_receiver,
+        rpc_status,
+        Controller::new(control),
+        args,
+    )
+    .await;
+
+    if exit_on_complete {
+        let _ = completion.send(result);
+    } else {
+        result?;
+    }
+    future::pending().await
+}
+
+/// Waits for the node to exit or, if `completion` is set, for the [`exex`] run to complete.
+pub async fn wait_for_exit(
+    node_exit: impl Future<Output = eyre::Result<()>>,
+    completion: Option<oneshot::Receiver<eyre::Result<()>>>,
+) -> eyre::Result<()> {
+    let Some(completion) = completion else { return node_exit.await };
+
+    match future::select(pin!(node_exit), completion).await {
+        Either::Left((result, _)) => result,
+        Either::Right((result, _)) => {
+            let result = result?;
+            match &result {
+                Ok(()) => info!("Tester run completed, shutting down."),
+                Err(err) => warn!(%err, "Tester run failed, shutting down."),
+            }
+            result
+        }
+    }
+}
 
-/// Uses etherscan to move the chain forward **until** it has collected `num_blocks`.
-pub async fn exex<Node: FullNodeComponents>(
-    mut ctx: ExExContext<Node>,
+/// Moves the chain forward until a stop condition is met, and runs the [`RpcTester`] if
+/// `--against-rpc` is set.
+///
+/// Unless `--exit-on-complete` is set, it then waits for [`ControlCommand`]s moving the chain
+/// further, and only returns on `tester_stop`.
+#[allow(clippy::too_many_arguments)]
+async fn run<Node: FullNodeComponents, T: PayloadTransaction>(
+    components: &Node,
+    notifications: &NotificationTracker,
+    wal_dir: &Path,
+    fake_cl: FakeCl<T>,
     server_receiver: oneshot::Receiver<(AuthServerHandle, RpcServerHandle)>,
     rpc_status: Arc<RwLock<TesterStatus>>,
+    mut control: Controller,
     args: TestArgs,
 ) -> eyre::Result<()> {
-    let TestArgs { etherscan_url, num_blocks, against_rpc } = args;
+    let stop_conditions = args.stop_conditions();
+    let verifier = args.notification_verifier()?;
+    let TestArgs {
+        against_rpc,
+        reorg_every,
+        reorg_depth,
+        bench,
+        exex_replay,
+        exit_on_complete,
+        devnet,
+        ..
+    } = args;
     let (auth_handle, rpc_handle) = server_receiver.await?;
     let auth_client = auth_handle.http_client();
-    let finalized = ctx.provider().finalized_block_num_hash()?.unwrap_or_default();
-    let mut fake_cl = FakeCl::new(ctx.config.chain.chain(), etherscan_url)?;
-    let initial_height = ctx.provider().last_block_number()?;
+    let finalized = components.provider().finalized_block_num_hash()?.unwrap_or_default();
+    let initial_height = components.provider().last_block_number()?;
 
-    let mut local_tip = initial_height;
-    let mut etherscan_tip =
-        fake_cl.etherscan.load_block(BlockNumberOrTag::Latest).await?.header.number;
+    let mut stop = StopTracker::new(stop_conditions, initial_height);
+    let mut runner = Runner::new(fake_cl, initial_height, bench).await?;
+    let mut completed = false;
+    // Competing branch left canonical by `tester_reorg`, replaced by the next submitted block
+    let mut pending_reorg: Option<Reorg> = None;
 
     rpc_status.write().initial_height = initial_height;
 
-    info!(local_tip, etherscan_tip, ?finalized, "Starting exex.");
+    info!(
+        local_tip = initial_height,
+        source_tip = runner.source_tip(),
+        ?finalized,
+        "Starting exex."
+    );
     loop {
+        let local_tip = runner.local_tip();
+        control.poll(local_tip);
+        if let Some(target) = control.take_target() {
+            stop.set_target(target);
+        }
+
         // StageId::Bodies gets updated on each flush to disk
-        let storage_tip =
-            ctx.provider().get_stage_checkpoint(StageId::Bodies)?.unwrap_or_default().block_number;
+        let storage_tip = components
+            .provider()
+            .get_stage_checkpoint(StageId::Bodies)?
+            .unwrap_or_default()
+            .block_number;
+        stop.observe_storage_tip(storage_tip);
+
+        // Injects the reorgs requested through `tester_reorg`
+        while let Some((depth, switch_back, reply)) = control.take_reorg() {
+            if pending_reorg.is_some() {
+                let _ = reply.send(Err("a reorg is already canonical until the next block".into()));
+                continue
+            }
+            // Devnet blocks are built on top of the node head, so they would extend the branch
+            if devnet && !switch_back {
+                let _ = reply.send(Err("devnet reorgs must switch back to the built chain".into()));
+                continue
+            }
+            if depth == 0 || depth > MAX_REORG_DEPTH as u64 {
+                let _ =
+                    reply.send(Err(format!("reorg depth must be between 1 and {MAX_REORG_DEPTH}")));
+                continue
+            }
+            if depth > local_tip.saturating_sub(storage_tip) {
+                let _ = reply.send(Err(format!(
+                    "reorg depth {depth} is beyond the in-memory range ({storage_tip}, {local_tip}]"
+                )));
+                continue
+            }
+
+            let reorg = inject_reorg::<<Node::Types as NodeTypesWithEngine>::Engine, _>(
+                runner.fake_cl_mut(),
+                &auth_client,
+                notifications,
+                verifier.as_ref(),
+                &rpc_status,
+                depth,
+                finalized.hash,
+                switch_back,
+            )
+            .await;
+            // The run fails anyway, but the caller learns why
+            let reorg = match reorg {
+                Ok(reorg) => reorg,
+                Err(err) => {
+                    let _ = reply.send(Err(err.to_string()));
+                    return Err(err)
+                }
+            };
+            if !switch_back {
+                pending_reorg = Some(reorg.clone());
+                rpc_status.write().reorg = Some(reorg.clone());
+            }
+            let _ = reply.send(Ok(ReorgReport { reorg, switched_back: switch_back }));
+        }
 
         // Updates the `tester/status`
         {
             let mut rpc_status_rw = rpc_status.write();
             rpc_status_rw.in_memory_first = storage_tip + 1;
             rpc_status_rw.tip = local_tip;
+            rpc_status_rw.paused = control.is_paused();
         }
 
-        // Have reached the maximum number of blocks so we can exit the exex
-        if local_tip >= initial_height + num_blocks {
-            info!(
-                etherscan_tip,
-                local_tip, storage_tip, initial_height, "Stopped moving chain forward"
-            );
+        // Stepping through `tester_advance` ignores stop conditions
+        let condition = stop.met(local_tip).filter(|_| !control.is_stepping());
 
-            // Updates the `tester/status` with ready
-            rpc_status.write().ready = true;
+        // Have met a stop condition so we can complete the run, once
+        if condition.is_some() || control.is_stopped() {
+            if !completed {
+                info!(
+                    source_tip = runner.source_tip(),
+                    local_tip,
+                    storage_tip,
+                    initial_height,
+                    ?condition,
+                    "Stopped moving chain forward"
+                );
+                completed = true;
 
-            if let Some(remote_url) = against_rpc {
-                RpcTester::new(
-                    rpc_handle.http_client().expect("should have rpc"),
-                    HttpClientBuilder::default().build(&remote_url)?,
-                )
-                .test_equality((storage_tip - 2)..=local_tip)
-                .await?;
+                // Updates the `tester/status` with ready, and the bench results if any
+                {
+                    let mut rpc_status_rw = rpc_status.write();
+                    rpc_status_rw.ready = true;
+                    if let Some(summary) = runner.take_bench_summary() {
+                        rpc_status_rw.bench = Some(summary);
+                    }
+                }
 
-                return Ok(())
+                // Compares a backfill job and an ExEx restarted from the WAL with the live
+                // notifications of this run
+                if exex_replay && local_tip > initial_height {
+                    let live = notifications.history();
+                    let backfilled_blocks =
+                        check_backfill(components, &live, initial_height + 1..=local_tip).await?;
+                    let tip_hash = components
+                        .provider()
+                        .block_hash(local_tip)?
+                        .ok_or_else(|| eyre::eyre!("missing canonical block {local_tip}"))?;
+                    let wal_restart = check_wal_restart(
+                        components,
+                        wal_dir,
+                        &live,
+                        BlockNumHash::new(local_tip, tip_hash),
+                    )
+                    .await?;
+                    info!(backfilled_blocks, ?wal_restart, "Replayed live notifications");
+                    rpc_status.write().replay =
+                        Some(ReplayStatus { backfilled_blocks, wal_restart });
+                }
+
+                if let Some(remote_url) = &against_rpc {
+                    RpcTester::new(
+                        rpc_handle.http_client().expect("should have rpc"),
+                        HttpClientBuilder::default().build(remote_url)?,
+                    )
+                    .test_equality((storage_tip - 2)..=local_tip)
+                    .await?;
+                }
             }
 
-            // Exiting would crash the node, so we sleep forever instead.
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(500)).await
+            if exit_on_complete || control.is_stopped() {
+                return Ok(())
             }
         }
 
+        // Waits for control commands moving the chain further
+        if condition.is_some() || control.is_paused() {
+            rpc_status.write().ready = true;
+            control.wait(local_tip).await;
+            continue
+        }
+
         // Query the next block
-        if local_tip < etherscan_tip {
-            info!(etherscan_tip, local_tip, storage_tip, "Advancing chain");
+        if !runner.is_caught_up() {
+            rpc_status.write().ready = false;
 
-            local_tip += 1;
-            fake_cl
-                .advance_chain::<<Node::Types as NodeTypesWithEngine>::Engine>(
+            // Updates the `tester/status` with the payload outcome
+            let outcome = runner
+                .advance::<<Node::Types as NodeTypesWithEngine>::Engine>(
                     &auth_client,
-                    local_tip,
                     finalized.hash,
+                    |outcome| rpc_status.write().last_payload = Some(outcome.clone()),
                 )
                 .await?;
+            stop.record(&outcome);
+            let local_tip = runner.local_tip();
 
-            if let Some(notification) = ctx.notifications.try_next().await? {
-                match &notification {
-                    ExExNotification::ChainCommitted { new } => {
-                        info!(committed_chain = ?new.range(), "Received commit");
-                    }
-                    ExExNotification::ChainReorged { old, new } => {
-                        info!(from_chain = ?old.range(), to_chain = ?new.range(), "Received reorg");
-                    }
-                    ExExNotification::ChainReverted { old } => {
-                        info!(reverted_chain = ?old.range(), "Received revert");
-                    }
-                };
+            // A block on top of the source chain replaces the branch left by `tester_reorg`
+            let (key, expected) = match pending_reorg.take() {
+                Some(reorg) => {
+                    rpc_status.write().reorg = None;
+                    let mut expected = replaced_blocks(runner.fake_cl(), &reorg);
+                    expected.push(outcome.block_hash);
+                    (NotificationKey::reorg(reorg.new_head, outcome.block_hash), expected)
+                }
+                None => (NotificationKey::commit(outcome.block_hash), vec![outcome.block_hash]),
+            };
+            let notification = notifications.expect(key, outcome.block_number).await?;
+            verify_notification(
+                verifier.as_ref(),
+                &notification,
+                runner.fake_cl(),
+                &expected,
+                &rpc_status,
+            )
+            .await?;
 
-                if let Some(committed_chain) = notification.committed_chain() {
-                    // let b = BlockNumHash::new()
-                    ctx.events.send(ExExEvent::FinishedHeight(committed_chain.tip().num_hash()))?;
+            // Injects a reorg of the last `reorg_depth` blocks and switches back to the source
+            // chain afterwards
+            if reorg_every.is_some_and(|every| (local_tip - initial_height) % every == 0) {
+                if reorg_depth > local_tip.saturating_sub(storage_tip) {
+                    warn!(
+                        reorg_depth,
+                        local_tip, storage_tip, "Skipping reorg beyond in-memory range"
+                    );
+                } else {
+                    inject_reorg::<<Node::Types as NodeTypesWithEngine>::Engine, _>(
+                        runner.fake_cl_mut(),
+                        &auth_client,
+                        notifications,
+                        verifier.as_ref(),
+                        &rpc_status,
+                        reorg_depth,
+                        finalized.hash,
+                        true,
+                    )
+                    .await?;
                 }
             }
         }
 
-        // Avoids hitting rate limits
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        // Only polls the source once caught up to it, requests are paced by the block source
+        if runner.is_caught_up() {
+            runner.poll_source().await;
+        }
+    }
+}
+
+/// Makes a competing branch replacing the last `depth` blocks canonical and waits for its
+/// notification. If `switch_back`, the source chain is then made canonical again and its
+/// notification awaited too.
+#[allow(clippy::too_many_arguments)]
+async fn inject_reorg<E: EngineTypes, T: PayloadTransaction>(
+    fake_cl: &mut FakeCl<T>,
+    auth_client: &HttpClient<AuthClientService<HttpBackend>>,
+    notifications: &NotificationTracker,
+    verifier: Option<&NotificationVerifier>,
+    rpc_status: &RwLock<TesterStatus>,
+    depth: u64,
+    finalized_hash: BlockHash,
+    switch_back: bool,
+) -> eyre::Result<Reorg> {
+    let reorg = fake_cl.reorg::<E>(auth_client, depth, finalized_hash).await?;
+    let notification = notifications
+        .expect(NotificationKey::reorg(reorg.old_head, reorg.new_head), reorg.head_number)
+        .await?;
+    verify_notification(verifier, &notification, fake_cl, &reorg.new_blocks, rpc_status).await?;
+
+    if switch_back {
+        fake_cl.switch_back::<E>(auth_client, &reorg, finalized_hash).await?;
+        let notification = notifications
+            .expect(NotificationKey::reorg(reorg.new_head, reorg.old_head), reorg.head_number)
+            .await?;
+        verify_notification(
+            verifier,
+            &notification,
+            fake_cl,
+            &replaced_blocks(fake_cl, &reorg),
+            rpc_status,
+        )
+        .await?;
+    }
+
+    Ok(reorg)
+}
+
+/// Returns the hashes of the source blocks replaced by `reorg`.
+fn replaced_blocks<T: PayloadTransaction>(fake_cl: &FakeCl<T>, reorg: &Reorg) -> Vec<BlockHash> {
+    (reorg.head_number + 1 - reorg.depth..=reorg.head_number)
+        .filter_map(|number| fake_cl.canonical_block(number))
+        .map(|block| block.header.hash)
+        .collect()
+}
+
+/// Verifies that the committed chain of `notification` is made of the `expected` blocks and
+/// matches them, if a [`NotificationVerifier`] is set.
+async fn verify_notification<T: PayloadTransaction>(
+    verifier: Option<&NotificationVerifier>,
+    notification: &ExExNotification,
+    fake_cl: &FakeCl<T>,
+    expected: &[BlockHash],
+    rpc_status: &RwLock<TesterStatus>,
+) -> eyre::Result<()> {
+    let Some(verifier) = verifier else { return Ok(()) };
+
+    let verified = verifier.verify(notification, fake_cl, expected).await?;
+    rpc_status.write().verified_blocks += verified as u64;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_the_node_without_completion() {
+        let result = wait_for_exit(async { eyre::bail!("node crashed") }, None).await;
+        assert_eq!(result.unwrap_err().to_string(), "node crashed");
+    }
+
+    #[tokio::test]
+    async fn exits_with_the_run_result() {
+        let (completion, receiver) = oneshot::channel();
+        completion.send(Ok(())).unwrap();
+        wait_for_exit(future::pending(), Some(receiver)).await.unwrap();
+
+        let (completion, receiver) = oneshot::channel();
+        completion.send(Err(eyre::eyre!("block 10 is INVALID"))).unwrap();
+        let result = wait_for_exit(future::pending(), Some(receiver)).await;
+        assert_eq!(result.unwrap_err().to_string(), "block 10 is INVALID");
+    }
+
+    #[tokio::test]
+    async fn fails_if_the_run_never_completes() {
+        let (completion, receiver) = oneshot::channel::<eyre::Result<()>>();
+        drop(completion);
+        assert!(wait_for_exit(future::pending(), Some(receiver)).await.is_err());
 
-        etherscan_tip = fake_cl.etherscan.load_block(BlockNumberOrTag::Latest).await?.header.number
+        let (_completion, receiver) = oneshot::channel();
+        let result = wait_for_exit(async { eyre::bail!("node crashed") }, Some(receiver)).await;
+        assert_eq!(result.unwrap_err().to_string(), "node crashed");
     }
 }
@@ -1,8 +1,19 @@
+use super::{
+    control::{ControlCommand, ReorgReport},
+    notifications::NotificationStatus,
+    replay::ReplayStatus,
+};
 use alloy_primitives::BlockNumber;
-use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use fake_cl::{bench::BenchSummary, reorg::Reorg, status::PayloadOutcome};
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    proc_macros::rpc,
+    types::{error::INTERNAL_ERROR_CODE, ErrorObjectOwned},
+};
 use parking_lot::RwLock;
 use serde::Serialize;
 use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
 
 /// trait interface for a custom rpc namespace: `tester`
 #[rpc(server, namespace = "tester")]
@@ -10,6 +21,32 @@ pub trait TesterExtApi {
     /// Returns [`TesterStatus`]
     #[method(name = "status")]
     fn status(&self) -> RpcResult<TesterStatus>;
+
+    /// Submits `blocks` more blocks, ignoring stop conditions, then pauses. Returns the new tip.
+    #[method(name = "advance")]
+    async fn advance(&self, blocks: u64) -> RpcResult<BlockNumber>;
+
+    /// Pauses after the block being submitted. Returns the tip.
+    #[method(name = "pause")]
+    async fn pause(&self) -> RpcResult<BlockNumber>;
+
+    /// Resumes moving the chain forward until a stop condition is met.
+    #[method(name = "resume")]
+    fn resume(&self) -> RpcResult<()>;
+
+    /// Replaces the stop conditions by reaching `block`.
+    #[method(name = "setTarget")]
+    fn set_target(&self, block: BlockNumber) -> RpcResult<()>;
+
+    /// Stops moving the chain forward and completes the run.
+    #[method(name = "stop")]
+    fn stop(&self) -> RpcResult<()>;
+
+    /// Makes a competing branch replacing the last `depth` blocks canonical. The source chain is
+    /// made canonical again right after if `switch_back`, or by the next submitted block
+    /// otherwise. Returns the old and new head hashes.
+    #[method(name = "reorg")]
+    async fn reorg(&self, depth: u64, switch_back: Option<bool>) -> RpcResult<ReorgReport>;
 }
 
 /// The type that implements the `Tester` rpc namespace trait
@@ -18,12 +55,26 @@ pub trait TesterExtApi {
 pub struct TesterStatus {
     /// Whether it has stopped advancing the chain forward
     pub ready: bool,
+    /// Whether it was paused through `tester_pause` or `tester_advance`.
+    pub paused: bool,
     /// Initial block number
     pub initial_height: BlockNumber,
     /// Latest block number
     pub tip: BlockNumber,
     /// First block number in-memory.
     pub in_memory_first: BlockNumber,
+    /// Outcome of the last submitted payload.
+    pub last_payload: Option<PayloadOutcome>,
+    /// Competing branch made canonical by `tester_reorg`, until the next submitted block.
+    pub reorg: Option<Reorg>,
+    /// Health of the ExEx notification stream.
+    pub notifications: NotificationStatus,
+    /// Number of blocks of ExEx notifications checked with `--verify-notifications`.
+    pub verified_blocks: u64,
+    /// Backfill & WAL replay results, once done advancing the chain with `--exex-replay`.
+    pub replay: Option<ReplayStatus>,
+    /// Engine performance, once done advancing the chain with `--bench`.
+    pub bench: Option<BenchSummary>,
 }
 
 /// The type that implements the `Tester` rpc namespace trait
@@ -31,16 +82,66 @@ pub struct TesterStatus {
 #[derive(Debug, Clone)]
 pub struct TesterExt {
     pub watcher: Arc<RwLock<TesterStatus>>,
+    /// Sends [`ControlCommand`]s to the loop moving the chain forward.
+    control: mpsc::UnboundedSender<ControlCommand>,
 }
 
 impl TesterExt {
-    pub fn new() -> Self {
-        Self { watcher: Arc::new(RwLock::new(TesterStatus::default())) }
+    /// Creates a [`Self`] and the receiver of its [`ControlCommand`]s.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<ControlCommand>) {
+        let (control, commands) = mpsc::unbounded_channel();
+        (Self { watcher: Arc::new(RwLock::new(TesterStatus::default())), control }, commands)
+    }
+
+    fn send(&self, command: ControlCommand) -> RpcResult<()> {
+        self.control.send(command).map_err(|_| control_error("tester is not controllable"))
+    }
+
+    /// Sends the command built by `command` and waits for its reply.
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> ControlCommand,
+    ) -> RpcResult<T> {
+        let (reply, receiver) = oneshot::channel();
+        self.send(command(reply))?;
+        receiver.await.map_err(|_| control_error("tester stopped before replying"))
     }
 }
 
+#[async_trait]
 impl TesterExtApiServer for TesterExt {
     fn status(&self) -> RpcResult<TesterStatus> {
         Ok(self.watcher.read().clone())
     }
+
+    async fn advance(&self, blocks: u64) -> RpcResult<BlockNumber> {
+        self.request(|reply| ControlCommand::Advance { blocks, reply }).await
+    }
+
+    async fn pause(&self) -> RpcResult<BlockNumber> {
+        self.request(|reply| ControlCommand::Pause { reply }).await
+    }
+
+    fn resume(&self) -> RpcResult<()> {
+        self.send(ControlCommand::Resume)
+    }
+
+    fn set_target(&self, block: BlockNumber) -> RpcResult<()> {
+        self.send(ControlCommand::SetTarget(block))
+    }
+
+    fn stop(&self) -> RpcResult<()> {
+        self.send(ControlCommand::Stop)
+    }
+
+    async fn reorg(&self, depth: u64, switch_back: Option<bool>) -> RpcResult<ReorgReport> {
+        let switch_back = switch_back.unwrap_or_default();
+        self.request(|reply| ControlCommand::Reorg { depth, switch_back, reply })
+            .await?
+            .map_err(|err| control_error(&err))
+    }
+}
+
+fn control_error(message: &str) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(INTERNAL_ERROR_CODE, message, None::<()>)
 }
@@ -6,3 +6,18 @@ pub mod ext;
 
 /// cli arguments
 pub mod args;
+
+/// stop conditions
+pub mod stop;
+
+/// notification verification
+pub mod verify;
+
+/// notification tracking
+pub mod notifications;
+
+/// backfill & WAL replay
+pub mod replay;
+
+/// interactive control
+pub mod control;
@@ -0,0 +1,449 @@
+use super::ext::TesterStatus;
+use alloy_primitives::{BlockHash, BlockNumber};
+use futures::{Stream, StreamExt};
+use parking_lot::{Mutex, RwLock};
+use reth_exex::{ExExEvent, ExExNotification};
+use reth_tracing::tracing::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, oneshot};
+
+/// Identifies a notification by the tips of the chains it reverts and commits.
+///
+/// Switching back after a reorg commits the same chain again, but reverts a different one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NotificationKey {
+    /// Tip of the reverted chain, if any.
+    pub reverted: Option<BlockHash>,
+    /// Tip of the committed chain, if any.
+    pub committed: Option<BlockHash>,
+}
+
+impl NotificationKey {
+    /// Key of the notification committing `tip` on top of the canonical chain.
+    pub fn commit(tip: BlockHash) -> Self {
+        Self { reverted: None, committed: Some(tip) }
+    }
+
+    /// Key of the notification replacing the chain ending at `old_tip` by the one ending at
+    /// `new_tip`.
+    pub fn reorg(old_tip: BlockHash, new_tip: BlockHash) -> Self {
+        Self { reverted: Some(old_tip), committed: Some(new_tip) }
+    }
+
+    fn of(notification: &ExExNotification) -> Self {
+        Self {
+            reverted: notification.reverted_chain().map(|chain| chain.tip().hash()),
+            committed: notification.committed_chain().map(|chain| chain.tip().hash()),
+        }
+    }
+}
+
+/// Health of the notification stream, reported in `tester_status`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NotificationStatus {
+    /// Number of notifications received.
+    pub received: u64,
+    /// Number of notifications matched with the block that triggered them.
+    pub matched: u64,
+    /// Number of notifications received more than once.
+    pub duplicates: u64,
+    /// Number of notifications received without being triggered.
+    pub unexpected: u64,
+    /// Number of triggered notifications that never arrived.
+    pub missing: u64,
+    /// Tip of the last committed chain received.
+    pub last_committed: Option<BlockNumber>,
+    /// Number of triggering blocks whose notification is still awaited.
+    pub lag_blocks: u64,
+    /// Delay between the last matched trigger and its notification.
+    pub last_lag: Option<Duration>,
+    /// Largest delay between a trigger and its notification.
+    pub max_lag: Option<Duration>,
+}
+
+/// A notification triggered by an engine API call, awaited by the exex.
+struct Expectation {
+    key: NotificationKey,
+    block_number: BlockNumber,
+    triggered_at: Instant,
+    reply: oneshot::Sender<ExExNotification>,
+}
+
+/// Handle to the task draining the notification stream and correlating every notification with
+/// the block that triggered it.
+///
+/// The task acknowledges every committed chain with [`ExExEvent::FinishedHeight`] as soon as it's
+/// received, including after the exex stopped moving the chain forward.
+#[derive(Debug)]
+pub struct NotificationTracker {
+    expectations: mpsc::UnboundedSender<Expectation>,
+    status: Arc<RwLock<TesterStatus>>,
+    timeout: Duration,
+    /// Notifications received and not pruned yet, in order.
+    history: Arc<Mutex<Vec<ExExNotification>>>,
+}
+
+impl NotificationTracker {
+    /// Spawns the task draining `notifications`, acknowledging them through `events`.
+    ///
+    /// Triggered notifications not received within `timeout` are reported as missing.
+    ///
+    /// Notifications are only kept for [`Self::history`] if `history_finalized` is set. Like the
+    /// ExEx WAL, they are pruned once acknowledged at or below that finalized block.
+    pub fn spawn(
+        notifications: impl Stream<Item = eyre::Result<ExExNotification>> + Unpin + Send + 'static,
+        events: mpsc::UnboundedSender<ExExEvent>,
+        status: Arc<RwLock<TesterStatus>>,
+        timeout: Duration,
+        history_finalized: Option<BlockNumber>,
+    ) -> Self {
+        let (expectations, receiver) = mpsc::unbounded_channel();
+        let history = Arc::new(Mutex::new(Vec::new()));
+        let task = NotificationTask {
+            events,
+            status: status.clone(),
+            history: history.clone(),
+            history_finalized,
+            pending: VecDeque::new(),
+            early: VecDeque::new(),
+            seen: HashSet::new(),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = task.run(notifications, receiver).await {
+                warn!(%err, "Notification task stopped");
+            }
+        });
+
+        Self { expectations, status, timeout, history }
+    }
+
+    /// Returns the notifications received and not pruned so far, in order.
+    pub fn history(&self) -> Vec<ExExNotification> {
+        self.history.lock().clone()
+    }
+
+    /// Waits for the notification identified by `key`, triggered by submitting `block_number`.
+    pub async fn expect(
+        &self,
+        key: NotificationKey,
+        block_number: BlockNumber,
+    ) -> eyre::Result<ExExNotification> {
+        let (reply, receiver) = oneshot::channel();
+        self.expectations
+            .send(Expectation { key, block_number, triggered_at: Instant::now(), reply })
+            .map_err(|_| eyre::eyre!("notification task stopped"))?;
+
+        match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(Ok(notification)) => Ok(notification),
+            Ok(Err(_)) => {
+                eyre::bail!("notification of block {block_number} ({key:?}) was skipped")
+            }
+            Err(_) => {
+                self.status.write().notifications.missing += 1;
+                eyre::bail!(
+                    "timed out after {:?} waiting for the notification of block {block_number} ({key:?})",
+                    self.timeout
+                )
+            }
+        }
+    }
+}
+
+/// State of the task spawned by [`NotificationTracker`].
+struct NotificationTask {
+    events: mpsc::UnboundedSender<ExExEvent>,
+    status: Arc<RwLock<TesterStatus>>,
+    history: Arc<Mutex<Vec<ExExNotification>>>,
+    /// Finalized block below which acknowledged notifications are pruned from the history, if
+    /// recorded.
+    history_finalized: Option<BlockNumber>,
+    /// Triggered notifications not received yet, in trigger order.
+    pending: VecDeque<Expectation>,
+    /// Notifications received before being triggered, in arrival order.
+    early: VecDeque<(ExExNotification, Instant)>,
+    /// Every notification received so far.
+    seen: HashSet<NotificationKey>,
+}
+
+impl NotificationTask {
+    async fn run(
+        mut self,
+        mut notifications: impl Stream<Item = eyre::Result<ExExNotification>> + Unpin,
+        mut expectations: mpsc::UnboundedReceiver<Expectation>,
+    ) -> eyre::Result<()> {
+        loop {
+            tokio::select! {
+                notification = notifications.next() => match notification {
+                    Some(notification) => self.on_notification(notification?)?,
+                    None => eyre::bail!("notification stream ended"),
+                },
+                Some(expectation) = expectations.recv() => self.on_expectation(expectation),
+            }
+            self.update_lag();
+        }
+    }
+
+    /// Acknowledges `notification`, and hands it to the exex if it was triggered.
+    fn on_notification(&mut self, notification: ExExNotification) -> eyre::Result<()> {
+        match &notification {
+            ExExNotification::ChainCommitted { new } => {
+                info!(committed_chain = ?new.range(), "Received commit");
+            }
+            ExExNotification::ChainReorged { old, new } => {
+                info!(from_chain = ?old.range(), to_chain = ?new.range(), "Received reorg");
+            }
+            ExExNotification::ChainReverted { old } => {
+                info!(reverted_chain = ?old.range(), "Received revert");
+            }
+        };
+
+        if let Some(committed_chain) = notification.committed_chain() {
+            self.events.send(ExExEvent::FinishedHeight(committed_chain.tip().num_hash()))?;
+            self.status.write().notifications.last_committed = Some(committed_chain.tip().number);
+        }
+        self.status.write().notifications.received += 1;
+        self.record(&notification);
+
+        let key = NotificationKey::of(&notification);
+        let triggered = self.pending.iter().any(|expectation| expectation.key == key);
+        if !self.seen.insert(key) && !triggered {
+            warn!(?key, "Received duplicate notification");
+            self.status.write().notifications.duplicates += 1;
+            return Ok(())
+        }
+
+        match self.pending.iter().position(|expectation| expectation.key == key) {
+            Some(position) => {
+                // Every notification triggered before this one was skipped
+                for expectation in self.pending.drain(..position) {
+                    self.skip(expectation);
+                }
+                let expectation = self.pending.pop_front().expect("matched");
+                self.fulfill(expectation, notification, Instant::now());
+            }
+            None => self.early.push_back((notification, Instant::now())),
+        }
+        Ok(())
+    }
+
+    /// Hands the notification triggered by `expectation` to the exex if already received, or
+    /// waits for it.
+    fn on_expectation(&mut self, expectation: Expectation) {
+        let Some(position) = self
+            .early
+            .iter()
+            .position(|(notification, _)| NotificationKey::of(notification) == expectation.key)
+        else {
+            self.pending.push_back(expectation);
+            return
+        };
+
+        // Notifications received before this one were never triggered
+        for (notification, _) in self.early.drain(..position) {
+            warn!(key = ?NotificationKey::of(&notification), "Received unexpected notification");
+            self.status.write().notifications.unexpected += 1;
+        }
+        let (notification, received_at) = self.early.pop_front().expect("matched");
+        self.fulfill(expectation, notification, received_at);
+    }
+
+    /// Adds `notification` to the history if recorded, and prunes what the WAL would.
+    fn record(&mut self, notification: &ExExNotification) {
+        let Some(finalized) = self.history_finalized else { return };
+
+        let mut history = self.history.lock();
+        history.push(notification.clone());
+        if let Some(finished) = notification.committed_chain().map(|chain| chain.tip().number) {
+            let pruned = finished.min(finalized);
+            history.retain(|notification| {
+                notification
+                    .committed_chain()
+                    .or_else(|| notification.reverted_chain())
+                    .map_or(true, |chain| chain.tip().number > pruned)
+            });
+        }
+    }
+
+    fn fulfill(
+        &mut self,
+        expectation: Expectation,
+        notification: ExExNotification,
+        received_at: Instant,
+    ) {
+        let lag = received_at.saturating_duration_since(expectation.triggered_at);
+        {
+            let mut status = self.status.write();
+            status.notifications.matched += 1;
+            status.notifications.last_lag = Some(lag);
+            status.notifications.max_lag = status.notifications.max_lag.max(Some(lag));
+        }
+        // The exex may have given up waiting
+        let _ = expectation.reply.send(notification);
+    }
+
+    fn skip(&mut self, expectation: Expectation) {
+        // Timed out expectations were already reported by the exex
+        if !expectation.reply.is_closed() {
+            warn!(key = ?expectation.key, block_number = expectation.block_number, "Notification skipped");
+            self.status.write().notifications.missing += 1;
+        }
+    }
+
+    fn update_lag(&mut self) {
+        self.pending.retain(|expectation| !expectation.reply.is_closed());
+        self.status.write().notifications.lag_blocks = self.pending.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth::{
+        primitives::{Header, SealedBlockWithSenders, SealedHeader},
+        providers::{Chain, ExecutionOutcome},
+    };
+
+    fn chain(number: BlockNumber) -> Arc<Chain> {
+        let mut block = SealedBlockWithSenders::default();
+        block.block.header = SealedHeader::new(
+            Header { number, ..Default::default() },
+            BlockHash::with_last_byte(number as u8),
+        );
+        Arc::new(Chain::from_block(block, ExecutionOutcome::default(), None))
+    }
+
+    fn commit(number: BlockNumber) -> ExExNotification {
+        ExExNotification::ChainCommitted { new: chain(number) }
+    }
+
+    fn hash(number: BlockNumber) -> BlockHash {
+        BlockHash::with_last_byte(number as u8)
+    }
+
+    fn task(
+        history_finalized: Option<BlockNumber>,
+    ) -> (NotificationTask, mpsc::UnboundedReceiver<ExExEvent>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let task = NotificationTask {
+            events,
+            status: Default::default(),
+            history: Default::default(),
+            history_finalized,
+            pending: VecDeque::new(),
+            early: VecDeque::new(),
+            seen: HashSet::new(),
+        };
+        (task, receiver)
+    }
+
+    fn expectation(
+        key: NotificationKey,
+        block_number: BlockNumber,
+    ) -> (Expectation, oneshot::Receiver<ExExNotification>) {
+        let (reply, receiver) = oneshot::channel();
+        (Expectation { key, block_number, triggered_at: Instant::now(), reply }, receiver)
+    }
+
+    #[test]
+    fn matches_notifications_with_their_trigger() {
+        let (mut task, mut events) = task(None);
+
+        // triggered before being received
+        let (first, mut first_reply) = expectation(NotificationKey::commit(hash(1)), 1);
+        task.on_expectation(first);
+        assert!(first_reply.try_recv().is_err());
+        task.on_notification(commit(1)).unwrap();
+        assert_eq!(first_reply.try_recv().unwrap().committed_chain().unwrap().tip().number, 1);
+
+        // received before being triggered
+        task.on_notification(commit(2)).unwrap();
+        let (second, mut second_reply) = expectation(NotificationKey::commit(hash(2)), 2);
+        task.on_expectation(second);
+        assert_eq!(second_reply.try_recv().unwrap().committed_chain().unwrap().tip().number, 2);
+
+        // every committed chain is acknowledged
+        for number in [1, 2] {
+            assert!(matches!(
+                events.try_recv(),
+                Ok(ExExEvent::FinishedHeight(tip)) if tip.number == number
+            ));
+        }
+
+        let status = task.status.read().notifications.clone();
+        assert_eq!((status.received, status.matched), (2, 2));
+        assert_eq!((status.missing, status.unexpected, status.duplicates), (0, 0, 0));
+        assert_eq!(status.last_committed, Some(2));
+    }
+
+    #[test]
+    fn reports_skipped_and_unexpected_notifications() {
+        let (mut task, _events) = task(None);
+
+        let (skipped, mut skipped_reply) = expectation(NotificationKey::commit(hash(1)), 1);
+        let (matched, mut matched_reply) = expectation(NotificationKey::commit(hash(2)), 2);
+        task.on_expectation(skipped);
+        task.on_expectation(matched);
+        task.update_lag();
+        assert_eq!(task.status.read().notifications.lag_blocks, 2);
+
+        task.on_notification(commit(2)).unwrap();
+        assert!(matches!(skipped_reply.try_recv(), Err(oneshot::error::TryRecvError::Closed)));
+        assert!(matched_reply.try_recv().is_ok());
+        task.update_lag();
+
+        task.on_notification(commit(3)).unwrap();
+        task.on_notification(commit(4)).unwrap();
+        let (late, mut late_reply) = expectation(NotificationKey::commit(hash(4)), 4);
+        task.on_expectation(late);
+        assert!(late_reply.try_recv().is_ok());
+
+        let status = task.status.read().notifications.clone();
+        assert_eq!((status.received, status.matched), (3, 2));
+        assert_eq!((status.missing, status.unexpected), (1, 1));
+        assert_eq!(status.lag_blocks, 0);
+    }
+
+    #[test]
+    fn tells_duplicates_from_switching_back() {
+        let (mut task, _events) = task(None);
+
+        task.on_notification(commit(1)).unwrap();
+        task.on_notification(commit(1)).unwrap();
+        assert_eq!(task.status.read().notifications.duplicates, 1);
+
+        // committing the same chain again after a reorg reverts a different one
+        let switch_back = NotificationKey::reorg(hash(2), hash(1));
+        let (expectation, mut reply) = expectation(switch_back, 1);
+        task.on_expectation(expectation);
+        task.on_notification(ExExNotification::ChainReorged { old: chain(2), new: chain(1) })
+            .unwrap();
+        assert!(reply.try_recv().is_ok());
+        assert_eq!(task.status.read().notifications.duplicates, 1);
+    }
+
+    #[test]
+    fn prunes_history_like_the_wal() {
+        let committed = |task: &NotificationTask| {
+            task.history
+                .lock()
+                .iter()
+                .map(|notification| notification.committed_chain().unwrap().tip().number)
+                .collect::<Vec<_>>()
+        };
+
+        let (mut task, _events) = task(None);
+        task.on_notification(commit(1)).unwrap();
+        assert!(committed(&task).is_empty());
+
+        let (mut task, _events) = self::task(Some(2));
+        for number in 1..=4 {
+            task.on_notification(commit(number)).unwrap();
+        }
+        assert_eq!(committed(&task), vec![3, 4]);
+    }
+}
@@ -0,0 +1,281 @@
+use alloy_eips::BlockNumHash;
+use alloy_primitives::{BlockHash, BlockNumber};
+use futures::TryStreamExt;
+use reth::{
+    api::FullNodeComponents,
+    primitives::{Head, Receipt},
+    providers::Chain,
+};
+use reth_exex::{
+    BackfillJobFactory, ExExHead, ExExNotification, ExExNotifications, ExExNotificationsStream, Wal,
+};
+use reth_tracing::tracing::info;
+use serde::Serialize;
+use std::{collections::BTreeMap, ops::RangeInclusive, path::Path};
+use tokio::sync::mpsc;
+
+/// Results of replaying the ExEx notifications of a run, reported in `tester_status`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayStatus {
+    /// Number of blocks re-executed by the backfill job and matching the live notifications.
+    pub backfilled_blocks: u64,
+    /// ExEx restarted from the WAL.
+    pub wal_restart: WalRestart,
+}
+
+/// An ExEx restarted from the WAL by [`check_wal_restart`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WalRestart {
+    /// Head the ExEx restarted from, if any block was committed during the run.
+    pub head: Option<BlockNumHash>,
+    /// Number of blocks reverted from the WAL.
+    pub reverted_blocks: u64,
+    /// Number of blocks committed afterwards.
+    pub committed_blocks: u64,
+}
+
+/// Canonical blocks and receipts committed by a sequence of notifications, by block number.
+type Committed = BTreeMap<BlockNumber, (BlockHash, Vec<Option<Receipt>>)>;
+
+/// Applies the reverted and committed chains of `notifications`, in order.
+fn committed<'a>(notifications: impl IntoIterator<Item = &'a ExExNotification>) -> Committed {
+    let mut committed = Committed::new();
+    for notification in notifications {
+        apply(&mut committed, notification);
+    }
+    committed
+}
+
+/// Applies the reverted and committed chains of `notification` to `committed`.
+fn apply(committed: &mut Committed, notification: &ExExNotification) {
+    if let Some(reverted) = notification.reverted_chain() {
+        for block in reverted.blocks_iter() {
+            committed.remove(&block.number);
+        }
+    }
+    if let Some(chain) = notification.committed_chain() {
+        insert_chain(committed, &chain);
+    }
+}
+
+fn insert_chain(committed: &mut Committed, chain: &Chain) {
+    for (block, receipts) in chain.blocks_and_receipts() {
+        committed.insert(block.number, (block.hash(), receipts.clone()));
+    }
+}
+
+/// Re-executes `range` with an ExEx backfill job and compares every block and its receipts with
+/// the ones committed by the `live` notifications.
+///
+/// Returns the number of compared blocks.
+pub async fn check_backfill<Node: FullNodeComponents>(
+    components: &Node,
+    live: &[ExExNotification],
+    range: RangeInclusive<BlockNumber>,
+) -> eyre::Result<u64> {
+    let live = committed(live);
+    let factory =
+        BackfillJobFactory::new(components.block_executor().clone(), components.provider().clone());
+
+    let mut backfilled = Committed::new();
+    let mut stream = factory.backfill(range.clone()).into_stream();
+    while let Some(chain) = stream.try_next().await? {
+        info!(range = ?chain.range(), "Backfilled chain");
+        insert_chain(&mut backfilled, &chain);
+    }
+
+    let mut compared = 0;
+    for number in range {
+        let Some((hash, receipts)) = live.get(&number) else {
+            eyre::bail!("block {number} was not committed by any live notification")
+        };
+        let Some((backfilled_hash, backfilled_receipts)) = backfilled.get(&number) else {
+            eyre::bail!("block {number} was not backfilled")
+        };
+        if hash != backfilled_hash {
+            eyre::bail!("backfilled block {number} is {backfilled_hash}, live is {hash}")
+        }
+        if receipts != backfilled_receipts {
+            eyre::bail!(
+                "backfilled receipts of block {number} differ from live ones: {backfilled_receipts:?}, live: {receipts:?}"
+            )
+        }
+        compared += 1;
+    }
+
+    Ok(compared)
+}
+
+/// Restarts an ExEx from a copy of the write-ahead log at `wal_dir`, with the node at
+/// `node_head`, and checks that it ends up with the same canonical chain as the `live`
+/// notifications.
+///
+/// The ExEx restarts from the tip of the last branch reorged out during the run, as if it crashed
+/// right after processing it: it must revert that branch from the WAL, then backfill the canonical
+/// chain. Without any reorg, it restarts from the parent of the first live block and only
+/// backfills.
+///
+/// The WAL is copied since the manager still owns it, which is only consistent once the chain
+/// stopped moving.
+pub async fn check_wal_restart<Node: FullNodeComponents>(
+    components: &Node,
+    wal_dir: &Path,
+    live: &[ExExNotification],
+    node_head: BlockNumHash,
+) -> eyre::Result<WalRestart> {
+    let canonical = committed(live);
+
+    // Tip of the last committed branch that is not canonical anymore
+    let reorged = live.iter().enumerate().rev().find_map(|(index, notification)| {
+        let tip = notification.committed_chain()?.tip().num_hash();
+        let canonical_hash = canonical.get(&tip.number).map(|(hash, _)| *hash);
+        (canonical_hash != Some(tip.hash)).then_some((index, tip))
+    });
+    let (mut state, exex_head) = match reorged {
+        Some((index, tip)) => (committed(&live[..=index]), tip),
+        None => {
+            let Some(first) = live.iter().find_map(|notification| notification.committed_chain())
+            else {
+                return Ok(WalRestart::default())
+            };
+            let first = first.first();
+            (Committed::new(), BlockNumHash::new(first.number - 1, first.parent_hash))
+        }
+    };
+
+    let snapshot = wal_dir.with_file_name("wal-replay");
+    copy_dir(wal_dir, &snapshot)?;
+    let wal = Wal::new(&snapshot)?;
+
+    // No live notification is sent to the restarted ExEx, so its stream ends once caught up
+    let (_, receiver) = mpsc::channel(1);
+    let mut notifications = ExExNotifications::new(
+        Head { number: node_head.number, hash: node_head.hash, ..Default::default() },
+        components.provider().clone(),
+        components.block_executor().clone(),
+        receiver,
+        wal.handle(),
+    )
+    .with_head(ExExHead { block: exex_head });
+
+    let mut restart = WalRestart { head: Some(exex_head), ..Default::default() };
+    while let Some(notification) = notifications.try_next().await? {
+        info!(
+            reverted = ?notification.reverted_chain().map(|chain| chain.range()),
+            committed = ?notification.committed_chain().map(|chain| chain.range()),
+            "Restarted ExEx notification"
+        );
+        if let Some(reverted) = notification.reverted_chain() {
+            restart.reverted_blocks += reverted.len() as u64;
+        }
+        if let Some(committed) = notification.committed_chain() {
+            restart.committed_blocks += committed.len() as u64;
+        }
+        apply(&mut state, &notification);
+    }
+
+    if reorged.is_some() && restart.reverted_blocks == 0 {
+        eyre::bail!("restarted ExEx did not revert the reorged out head {exex_head:?} from the WAL")
+    }
+    if let Some(number) = canonical
+        .keys()
+        .chain(state.keys())
+        .find(|number| canonical.get(number) != state.get(number))
+    {
+        eyre::bail!(
+            "block {number} of the restarted ExEx differs from the live one: {:?}, live: {:?}",
+            state.get(number),
+            canonical.get(number)
+        )
+    }
+
+    Ok(restart)
+}
+
+/// Copies the files of the `from` directory to the `to` directory, replacing its content.
+fn copy_dir(from: &Path, to: &Path) -> eyre::Result<()> {
+    if to.exists() {
+        std::fs::remove_dir_all(to)?;
+    }
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth::{
+        primitives::{Header, Receipts, SealedBlockWithSenders, SealedHeader},
+        providers::ExecutionOutcome,
+    };
+    use std::sync::Arc;
+
+    fn hash(branch: u8, number: BlockNumber) -> BlockHash {
+        let mut hash = BlockHash::ZERO;
+        hash[0] = branch;
+        hash[31] = number as u8;
+        hash
+    }
+
+    /// Returns the blocks of `range` on `branch`, without receipts.
+    fn chain(branch: u8, range: RangeInclusive<BlockNumber>) -> Arc<Chain> {
+        let blocks = range.clone().map(|number| {
+            let mut block = SealedBlockWithSenders::default();
+            block.block.header = SealedHeader::new(
+                Header { number, parent_hash: hash(branch, number - 1), ..Default::default() },
+                hash(branch, number),
+            );
+            block
+        });
+        let outcome = ExecutionOutcome {
+            receipts: Receipts { receipt_vec: range.clone().map(|_| Vec::new()).collect() },
+            first_block: *range.start(),
+            ..Default::default()
+        };
+        Arc::new(Chain::new(blocks, outcome, None))
+    }
+
+    fn hashes(committed: &Committed) -> Vec<(BlockNumber, BlockHash)> {
+        committed.iter().map(|(number, (hash, _))| (*number, *hash)).collect()
+    }
+
+    #[test]
+    fn applies_notifications_in_order() {
+        let notifications = [
+            ExExNotification::ChainCommitted { new: chain(0, 1..=3) },
+            ExExNotification::ChainReorged { old: chain(0, 2..=3), new: chain(1, 2..=3) },
+            ExExNotification::ChainCommitted { new: chain(1, 4..=5) },
+            ExExNotification::ChainReverted { old: chain(1, 5..=5) },
+        ];
+
+        assert_eq!(
+            hashes(&committed(&notifications)),
+            vec![(1, hash(0, 1)), (2, hash(1, 2)), (3, hash(1, 3)), (4, hash(1, 4))]
+        );
+        assert_eq!(
+            hashes(&committed(&notifications[..1])),
+            vec![(1, hash(0, 1)), (2, hash(0, 2)), (3, hash(0, 3))]
+        );
+    }
+
+    #[test]
+    fn replaces_directory_content() {
+        let root = std::env::temp_dir().join(format!("tester-{}-copy-dir", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (from, to) = (root.join("wal"), root.join("wal-replay"));
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::create_dir_all(&to).unwrap();
+        std::fs::write(from.join("0.wal"), b"notification").unwrap();
+        std::fs::write(to.join("stale.wal"), b"stale").unwrap();
+
+        copy_dir(&from, &to).unwrap();
+
+        assert_eq!(std::fs::read(to.join("0.wal")).unwrap(), b"notification");
+        assert!(!to.join("stale.wal").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use alloy_primitives::{BlockHash, BlockNumber};
+use fake_cl::status::PayloadOutcome;
+use std::str::FromStr;
+
+/// When the tester stops moving the chain forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCondition {
+    /// Once this many blocks were submitted on top of the initial height.
+    Blocks(u64),
+    /// Once this block number is reached.
+    BlockNumber(BlockNumber),
+    /// Once the block with this hash is submitted.
+    BlockHash(BlockHash),
+    /// Once this many blocks are in memory, but not persisted yet.
+    InMemory(u64),
+    /// Once this many persistence flushes happened.
+    Flushes(u64),
+    /// Once the submitted blocks used this much gas.
+    Gas(u64),
+    /// Once the submitted blocks included this many transactions.
+    Transactions(u64),
+}
+
+impl StopCondition {
+    /// Whether the condition depends on the persistence progress of an in-process node.
+    pub fn requires_storage(&self) -> bool {
+        matches!(self, Self::InMemory(_) | Self::Flushes(_))
+    }
+}
+
+impl FromStr for StopCondition {
+    type Err = String;
+
+    /// Parses `block:<N>`, `hash:<HASH>`, `in-memory:<N>`, `flushes:<N>`, `gas:<N>` or `txs:<N>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid stop condition {s}: expected <kind>:<value>"))?;
+        let number = || value.parse().map_err(|err| format!("invalid stop condition {s}: {err}"));
+
+        match kind {
+            "block" => number().map(Self::BlockNumber),
+            "hash" => value
+                .parse()
+                .map(Self::BlockHash)
+                .map_err(|err| format!("invalid stop condition {s}: {err}")),
+            "in-memory" => number().map(Self::InMemory),
+            "flushes" => number().map(Self::Flushes),
+            "gas" => number().map(Self::Gas),
+            "txs" => number().map(Self::Transactions),
+            _ => Err(format!(
+                "invalid stop condition {s}: expected block, hash, in-memory, flushes, gas or txs"
+            )),
+        }
+    }
+}
+
+/// Tracks the progress of a run against its [`StopCondition`]s, stopping once any is met.
+#[derive(Debug)]
+pub struct StopTracker {
+    conditions: Vec<StopCondition>,
+    initial_height: BlockNumber,
+    /// Last block number persisted to disk.
+    storage_tip: Option<BlockNumber>,
+    /// Number of times the persisted block number moved forward.
+    flushes: u64,
+    /// Hash of the last submitted block.
+    last_hash: Option<BlockHash>,
+    gas_used: u64,
+    transactions: u64,
+}
+
+impl StopTracker {
+    /// Creates a [`Self`] for a run starting at `initial_height`.
+    pub fn new(conditions: Vec<StopCondition>, initial_height: BlockNumber) -> Self {
+        Self {
+            conditions,
+            initial_height,
+            storage_tip: None,
+            flushes: 0,
+            last_hash: None,
+            gas_used: 0,
+            transactions: 0,
+        }
+    }
+
+    /// Replaces the stop conditions by reaching `target`.
+    pub fn set_target(&mut self, target: BlockNumber) {
+        self.conditions = vec![StopCondition::BlockNumber(target)];
+    }
+
+    /// Records a submitted block.
+    pub fn record(&mut self, outcome: &PayloadOutcome) {
+        self.last_hash = Some(outcome.block_hash);
+        self.gas_used += outcome.gas_used;
+        self.transactions += outcome.transaction_count;
+    }
+
+    /// Records the last persisted block number, counting a flush each time it moves forward.
+    ///
+    /// Flushes happening in between two calls are counted as one.
+    pub fn observe_storage_tip(&mut self, storage_tip: BlockNumber) {
+        if self.storage_tip.is_some_and(|previous| storage_tip > previous) {
+            self.flushes += 1;
+        }
+        self.storage_tip = Some(storage_tip);
+    }
+
+    /// Returns the first condition met with the chain at `local_tip`, if any.
+    pub fn met(&self, local_tip: BlockNumber) -> Option<StopCondition> {
+        let in_memory = self.storage_tip.map(|storage_tip| local_tip.saturating_sub(storage_tip));
+
+        self.conditions.iter().copied().find(|condition| match *condition {
+            StopCondition::Blocks(blocks) => local_tip >= self.initial_height + blocks,
+            StopCondition::BlockNumber(number) => local_tip >= number,
+            StopCondition::BlockHash(hash) => self.last_hash == Some(hash),
+            StopCondition::InMemory(blocks) => {
+                in_memory.is_some_and(|in_memory| in_memory >= blocks)
+            }
+            StopCondition::Flushes(flushes) => self.flushes >= flushes,
+            StopCondition::Gas(gas) => self.gas_used >= gas,
+            StopCondition::Transactions(transactions) => self.transactions >= transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::b256;
+    use reth::rpc::types::engine::{PayloadStatus, PayloadStatusEnum};
+    use std::time::Duration;
+
+    fn outcome(block_hash: BlockHash, gas_used: u64, transaction_count: u64) -> PayloadOutcome {
+        let valid = PayloadStatus::from_status(PayloadStatusEnum::Valid);
+        PayloadOutcome {
+            block_number: 1,
+            block_hash,
+            new_payload: valid.clone(),
+            forkchoice_updated: Some(valid),
+            new_payload_latency: Duration::ZERO,
+            forkchoice_updated_latency: Some(Duration::ZERO),
+            gas_used,
+            transaction_count,
+            attempts: 1,
+            mutations: Vec::new(),
+            divergences: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let hash = b256!("88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6");
+
+        assert_eq!("block:100".parse::<StopCondition>(), Ok(StopCondition::BlockNumber(100)));
+        assert_eq!(
+            format!("hash:{hash}").parse::<StopCondition>(),
+            Ok(StopCondition::BlockHash(hash))
+        );
+        assert_eq!("in-memory:3".parse::<StopCondition>(), Ok(StopCondition::InMemory(3)));
+        assert_eq!("flushes:2".parse::<StopCondition>(), Ok(StopCondition::Flushes(2)));
+        assert_eq!("gas:30000000".parse::<StopCondition>(), Ok(StopCondition::Gas(30_000_000)));
+        assert_eq!("txs:10".parse::<StopCondition>(), Ok(StopCondition::Transactions(10)));
+        assert!("block".parse::<StopCondition>().is_err());
+        assert!("block:latest".parse::<StopCondition>().is_err());
+        assert!("hash:0x12".parse::<StopCondition>().is_err());
+        assert!("blocks:10".parse::<StopCondition>().is_err());
+    }
+
+    #[test]
+    fn stops_on_first_met_condition() {
+        let mut tracker = StopTracker::new(
+            vec![
+                StopCondition::Blocks(10),
+                StopCondition::Gas(100),
+                StopCondition::Transactions(3),
+            ],
+            100,
+        );
+        assert_eq!(tracker.met(100), None);
+
+        tracker.record(&outcome(BlockHash::ZERO, 60, 1));
+        assert_eq!(tracker.met(101), None);
+
+        tracker.record(&outcome(BlockHash::ZERO, 60, 1));
+        assert_eq!(tracker.met(102), Some(StopCondition::Gas(100)));
+        assert_eq!(tracker.met(110), Some(StopCondition::Blocks(10)));
+
+        tracker.set_target(120);
+        assert_eq!(tracker.met(119), None);
+        assert_eq!(tracker.met(120), Some(StopCondition::BlockNumber(120)));
+    }
+
+    #[test]
+    fn stops_on_block_hash() {
+        let hash = BlockHash::with_last_byte(1);
+        let mut tracker = StopTracker::new(vec![StopCondition::BlockHash(hash)], 0);
+
+        tracker.record(&outcome(BlockHash::with_last_byte(2), 0, 0));
+        assert_eq!(tracker.met(1), None);
+
+        tracker.record(&outcome(hash, 0, 0));
+        assert_eq!(tracker.met(2), Some(StopCondition::BlockHash(hash)));
+    }
+
+    #[test]
+    fn tracks_persistence_progress() {
+        let mut tracker =
+            StopTracker::new(vec![StopCondition::InMemory(5), StopCondition::Flushes(2)], 0);
+
+        // unknown until the storage tip is first observed
+        assert_eq!(tracker.met(10), None);
+
+        tracker.observe_storage_tip(8);
+        assert_eq!(tracker.met(12), None);
+        assert_eq!(tracker.met(13), Some(StopCondition::InMemory(5)));
+
+        // an unchanged tip is not a flush
+        tracker.observe_storage_tip(8);
+        tracker.observe_storage_tip(12);
+        assert_eq!(tracker.met(14), None);
+
+        tracker.observe_storage_tip(14);
+        assert_eq!(tracker.met(14), Some(StopCondition::Flushes(2)));
+    }
+}
@@ -0,0 +1,292 @@
+use alloy_primitives::{keccak256, logs_bloom, BlockHash, BlockNumber, TxHash, U64};
+use fake_cl::{payload::PayloadTransaction, FakeCl};
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use reth::{
+    primitives::{Receipt, SealedBlockWithSenders},
+    providers::Chain,
+    rpc::types::{BlockNumberOrTag, Log},
+};
+use reth_exex::ExExNotification;
+use reth_tracing::tracing::{debug, info};
+use serde::Deserialize;
+
+/// Receipt fields returned by `eth_getBlockReceipts` that are compared with the notification.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteReceipt {
+    transaction_hash: TxHash,
+    block_hash: BlockHash,
+    /// Missing before Byzantium.
+    #[serde(default)]
+    status: Option<U64>,
+    cumulative_gas_used: U64,
+    logs: Vec<Log>,
+}
+
+/// Checks the chains carried by [`ExExNotification`]s against the blocks submitted by the
+/// [`FakeCl`] and, if available, the receipts of a remote node.
+#[derive(Debug)]
+pub struct NotificationVerifier {
+    /// Node serving `eth_getBlockReceipts` for the source chain.
+    receipts_client: Option<HttpClient>,
+}
+
+impl NotificationVerifier {
+    /// Creates a [`Self`] fetching remote receipts from `receipts_url`, if any.
+    pub fn new(receipts_url: Option<&str>) -> eyre::Result<Self> {
+        let receipts_client =
+            receipts_url.map(|url| HttpClientBuilder::default().build(url)).transpose()?;
+        Ok(Self { receipts_client })
+    }
+
+    /// Verifies the committed chain of `notification`, which must be made of the `expected`
+    /// blocks in order.
+    ///
+    /// Returns the number of verified blocks.
+    pub async fn verify<T: PayloadTransaction>(
+        &self,
+        notification: &ExExNotification,
+        fake_cl: &FakeCl<T>,
+        expected: &[BlockHash],
+    ) -> eyre::Result<usize> {
+        let Some(chain) = notification.committed_chain() else {
+            eyre::bail!("expected a committed chain, got {notification:?}")
+        };
+
+        let hashes: Vec<_> = chain.blocks_iter().map(|block| block.hash()).collect();
+        if hashes != expected {
+            eyre::bail!(
+                "committed chain {:?} has blocks {hashes:?}, expected {expected:?}",
+                chain.range()
+            )
+        }
+        verify_execution_outcome(&chain)?;
+
+        for (block, receipts) in chain.blocks_and_receipts() {
+            let source = fake_cl
+                .canonical_block(block.number)
+                .ok_or_else(|| eyre::eyre!("committed block {} was not submitted", block.number))?;
+            if source.header.hash != block.hash() {
+                eyre::bail!(
+                    "committed block {} is {}, submitted {}",
+                    block.number,
+                    block.hash(),
+                    source.header.hash
+                )
+            }
+            verify_block(block, source.transactions.txns(), receipts)?;
+            // Injected competing blocks are skipped, the remote node being on another chain
+            self.verify_remote_receipts(block, receipts).await?;
+        }
+
+        info!(range = ?chain.range(), "Verified notification");
+        Ok(hashes.len())
+    }
+
+    /// Compares `receipts` with the ones returned by the remote node for `block`.
+    async fn verify_remote_receipts(
+        &self,
+        block: &SealedBlockWithSenders,
+        receipts: &[Option<Receipt>],
+    ) -> eyre::Result<()> {
+        let Some(client) = &self.receipts_client else { return Ok(()) };
+
+        let remote: Option<Vec<RemoteReceipt>> = client
+            .request("eth_getBlockReceipts", rpc_params![BlockNumberOrTag::Number(block.number)])
+            .await?;
+        let Some(remote) = remote else {
+            debug!(block_number = block.number, "Remote receipts not found, skipping");
+            return Ok(())
+        };
+        if remote.first().is_some_and(|receipt| receipt.block_hash != block.hash()) {
+            debug!(block_number = block.number, "Remote node is on another chain, skipping");
+            return Ok(())
+        }
+        if remote.len() != receipts.len() {
+            eyre::bail!(
+                "block {} has {} receipts, remote has {}",
+                block.number,
+                receipts.len(),
+                remote.len()
+            )
+        }
+
+        for (index, (receipt, remote)) in receipts.iter().zip(&remote).enumerate() {
+            let receipt = receipt
+                .as_ref()
+                .ok_or_else(|| eyre::eyre!("block {} is missing receipt {index}", block.number))?;
+            let context =
+                || format!("receipt of {} in block {}", remote.transaction_hash, block.number);
+
+            if let Some(status) = remote.status {
+                if (status == U64::from(1)) != receipt.success {
+                    eyre::bail!(
+                        "{} has success {}, remote has status {status}",
+                        context(),
+                        receipt.success
+                    )
+                }
+            }
+            if remote.cumulative_gas_used.to::<u64>() != receipt.cumulative_gas_used {
+                eyre::bail!(
+                    "{} has cumulative gas used {}, remote has {}",
+                    context(),
+                    receipt.cumulative_gas_used,
+                    remote.cumulative_gas_used
+                )
+            }
+            if !receipt.logs.iter().eq(remote.logs.iter().map(|log| &log.inner)) {
+                eyre::bail!(
+                    "{} has logs {:?}, remote has {:?}",
+                    context(),
+                    receipt.logs,
+                    remote.logs
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the execution outcome of `chain` covers exactly its blocks.
+fn verify_execution_outcome(chain: &Chain) -> eyre::Result<()> {
+    let outcome = chain.execution_outcome();
+    let first_block: BlockNumber = *chain.range().start();
+
+    if outcome.first_block() != first_block {
+        eyre::bail!(
+            "execution outcome starts at block {}, expected {first_block}",
+            outcome.first_block()
+        )
+    }
+    if outcome.receipts().len() != chain.len() {
+        eyre::bail!(
+            "execution outcome has receipts for {} blocks, expected {}",
+            outcome.receipts().len(),
+            chain.len()
+        )
+    }
+
+    Ok(())
+}
+
+/// Checks a committed block and its receipts against the submitted `source` transactions.
+fn verify_block<'a, T: PayloadTransaction + 'a>(
+    block: &SealedBlockWithSenders,
+    source: impl Iterator<Item = &'a T>,
+    receipts: &[Option<Receipt>],
+) -> eyre::Result<()> {
+    let source: Vec<_> = source.map(|tx| keccak256(tx.encoded())).collect();
+    let transactions: Vec<_> = block.body.transactions.iter().map(|tx| tx.hash()).collect();
+    if transactions != source {
+        eyre::bail!(
+            "block {} has transactions {transactions:?}, submitted {source:?}",
+            block.number
+        )
+    }
+    if block.senders.len() != transactions.len() {
+        eyre::bail!(
+            "block {} has {} senders for {} transactions",
+            block.number,
+            block.senders.len(),
+            transactions.len()
+        )
+    }
+
+    let receipts = receipts
+        .iter()
+        .enumerate()
+        .map(|(index, receipt)| {
+            receipt
+                .as_ref()
+                .ok_or_else(|| eyre::eyre!("block {} is missing receipt {index}", block.number))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    if receipts.len() != transactions.len() {
+        eyre::bail!(
+            "block {} has {} receipts for {} transactions",
+            block.number,
+            receipts.len(),
+            transactions.len()
+        )
+    }
+
+    let gas_used = receipts.last().map(|receipt| receipt.cumulative_gas_used).unwrap_or_default();
+    if gas_used != block.gas_used {
+        eyre::bail!(
+            "block {} receipts used {gas_used} gas, header has {}",
+            block.number,
+            block.gas_used
+        )
+    }
+
+    let bloom = logs_bloom(receipts.iter().flat_map(|receipt| &receipt.logs));
+    if bloom != block.logs_bloom {
+        eyre::bail!("block {} receipt logs don't match the header logs bloom", block.number)
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth::{
+        primitives::{Header, Receipts, SealedHeader},
+        providers::ExecutionOutcome,
+        rpc::types::Transaction,
+    };
+
+    fn block(number: BlockNumber, gas_used: u64) -> SealedBlockWithSenders {
+        let mut block = SealedBlockWithSenders::default();
+        block.block.header = SealedHeader::new(
+            Header { number, gas_used, ..Default::default() },
+            BlockHash::with_last_byte(number as u8),
+        );
+        block
+    }
+
+    fn verify_empty_block(
+        block: &SealedBlockWithSenders,
+        receipts: &[Option<Receipt>],
+    ) -> eyre::Result<()> {
+        verify_block::<Transaction>(block, std::iter::empty(), receipts)
+    }
+
+    #[test]
+    fn checks_blocks_against_their_receipts() {
+        verify_empty_block(&block(1, 0), &[]).unwrap();
+
+        let err = verify_empty_block(&block(1, 21_000), &[]).unwrap_err();
+        assert_eq!(err.to_string(), "block 1 receipts used 0 gas, header has 21000");
+
+        let err = verify_empty_block(&block(1, 0), &[None]).unwrap_err();
+        assert_eq!(err.to_string(), "block 1 is missing receipt 0");
+
+        let err = verify_empty_block(&block(1, 0), &[Some(Receipt::default())]).unwrap_err();
+        assert_eq!(err.to_string(), "block 1 has 1 receipts for 0 transactions");
+    }
+
+    #[test]
+    fn checks_execution_outcome_range() {
+        let outcome = |first_block, blocks: usize| ExecutionOutcome {
+            receipts: Receipts { receipt_vec: vec![Vec::new(); blocks] },
+            first_block,
+            ..Default::default()
+        };
+        let chain = |outcome| Chain::new([block(5, 0), block(6, 0)], outcome, None);
+
+        verify_execution_outcome(&chain(outcome(5, 2))).unwrap();
+
+        let err = verify_execution_outcome(&chain(outcome(4, 2))).unwrap_err();
+        assert_eq!(err.to_string(), "execution outcome starts at block 4, expected 5");
+
+        let err = verify_execution_outcome(&chain(outcome(5, 1))).unwrap_err();
+        assert_eq!(err.to_string(), "execution outcome has receipts for 1 blocks, expected 2");
+    }
+}